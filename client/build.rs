@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=../proto/rusdb/rusdb.proto");
+    tonic_build::configure()
+        .build_client(true)
+        .build_server(false)
+        .compile(&["../proto/rusdb/rusdb.proto"], &["../proto/rusdb"])?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::proto::{
+    FindRequest, GetRequest, InsertRequest, RemoveRequest, ReplaceRequest, UpdateRequest,
+};
+use crate::{Client, ClientError, Filter, Update};
+use bson::{doc, Bson};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use tonic::{Code, Status};
+
+/// How `Collection::find` walks the collection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FindOptions {
+    pub limit: Option<u32>,
+    /// Return documents newest first instead of in insertion order.
+    pub reverse: bool,
+}
+
+/// A collection whose documents are stored as `T`.
+pub struct Collection<T> {
+    client: Client,
+    database: String,
+    name: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Collection<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            database: self.database.clone(),
+            name: self.name.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, ClientError> {
+    Ok(bson::from_slice(data)?)
+}
+
+impl<T> Collection<T> {
+    pub(crate) fn new(client: Client, database: &str, name: &str) -> Self {
+        Self {
+            client,
+            database: database.to_string(),
+            name: name.to_string(),
+            marker: PhantomData,
+        }
+    }
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T> Collection<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Inserts a document and returns its `_id` as text.
+    pub async fn insert_one(&self, doc: &T) -> Result<String, ClientError> {
+        let mut ids = self.insert_many(std::slice::from_ref(doc)).await?;
+        Ok(ids.remove(0))
+    }
+    /// Inserts every document, or none of them if any is rejected.
+    pub async fn insert_many(&self, docs: &[T]) -> Result<Vec<String>, ClientError> {
+        let docs = docs
+            .iter()
+            .map(bson::to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        // Repeating the insert cannot store a document twice when the ids
+        // are chosen here; the server rejects ids it already has.
+        let idempotent = docs.iter().all(|doc| doc.contains_key("_id"));
+        let documents = docs
+            .iter()
+            .map(bson::to_vec)
+            .collect::<Result<Vec<_>, _>>()?;
+        let request = InsertRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            documents,
+            return_old: false,
+            ordered: true,
+            atomic: true,
+        };
+        let response = self
+            .client
+            .call(request, idempotent, |mut client, request| async move {
+                client.insert(request).await
+            })
+            .await?;
+        let mut ids = Vec::with_capacity(response.inserts.len());
+        for insert in response.inserts {
+            if let Some(error) = insert.error {
+                let code = Code::from_i32(error.code);
+                return Err(Status::new(code, error.message).into());
+            }
+            ids.push(insert.id);
+        }
+        Ok(ids)
+    }
+    pub async fn find(&self, filter: Filter, options: FindOptions) -> Result<Vec<T>, ClientError> {
+        let request = FindRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            filter: Some(filter.to_bytes()?),
+            limit: options.limit,
+            reverse: options.reverse,
+        };
+        let response = self
+            .client
+            .call(request, true, |mut client, request| async move {
+                client.find(request).await
+            })
+            .await?;
+        response.documents.iter().map(|data| decode(data)).collect()
+    }
+    pub async fn find_one(&self, filter: Filter) -> Result<Option<T>, ClientError> {
+        let options = FindOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        Ok(self.find(filter, options).await?.pop())
+    }
+    /// Looks a document up by the exact value and type of its `_id`.
+    pub async fn get(&self, id: impl Into<Bson>) -> Result<Option<T>, ClientError> {
+        let request = GetRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            id: String::new(),
+            id_document: Some(bson::to_vec(&doc! { "_id": id.into() })?),
+        };
+        let response = self
+            .client
+            .call(request, true, |mut client, request| async move {
+                client.get(request).await
+            })
+            .await?;
+        response.document.as_deref().map(decode).transpose()
+    }
+    /// Applies `update` to up to `limit` matching documents and returns them
+    /// as stored afterwards.
+    pub async fn update(
+        &self,
+        filter: Filter,
+        update: Update,
+        limit: Option<u32>,
+    ) -> Result<Vec<T>, ClientError> {
+        let request = UpdateRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            filter: filter.to_bytes()?,
+            updates: update.to_bytes()?,
+            limit,
+        };
+        let response = self
+            .client
+            .call(request, false, |mut client, request| async move {
+                client.update(request).await
+            })
+            .await?;
+        response.updated.iter().map(|data| decode(data)).collect()
+    }
+    /// Replaces the first matching document, keeping its `_id`, and returns
+    /// the stored document.
+    pub async fn replace(&self, filter: Filter, doc: &T) -> Result<Option<T>, ClientError> {
+        let request = ReplaceRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            filter: filter.to_bytes()?,
+            document: bson::to_vec(doc)?,
+        };
+        let response = self
+            .client
+            .call(request, false, |mut client, request| async move {
+                client.replace(request).await
+            })
+            .await?;
+        response.document.as_deref().map(decode).transpose()
+    }
+    /// Removes up to `limit` matching documents and returns how many went.
+    pub async fn remove(&self, filter: Filter, limit: Option<u32>) -> Result<u32, ClientError> {
+        let request = RemoveRequest {
+            collection: self.name.clone(),
+            database: self.database.clone(),
+            filter: filter.to_bytes()?,
+            limit,
+        };
+        let response = self
+            .client
+            .call(request, limit.is_none(), |mut client, request| async move {
+                client.remove(request).await
+            })
+            .await?;
+        Ok(response.count)
+    }
+}
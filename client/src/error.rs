@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use tonic::{Code, Status};
+
+/// Everything a client call can fail with.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server could not be reached or the endpoint is invalid.
+    Transport(tonic::transport::Error),
+    /// The server answered with an error, after any retries.
+    Status(Status),
+    /// A TLS certificate or key could not be read.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A value could not be encoded to or decoded from BSON.
+    Bson(String),
+    Config(String),
+}
+
+impl ClientError {
+    /// The gRPC code of a `Status` error.
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            ClientError::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(err) => write!(f, "transport error: {}", err),
+            ClientError::Status(status) => {
+                write!(f, "{:?}: {}", status.code(), status.message())
+            }
+            ClientError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ClientError::Bson(reason) => write!(f, "invalid BSON: {}", reason),
+            ClientError::Config(reason) => write!(f, "invalid client configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<tonic::transport::Error> for ClientError {
+    fn from(err: tonic::transport::Error) -> Self {
+        ClientError::Transport(err)
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        ClientError::Status(status)
+    }
+}
+
+impl From<bson::ser::Error> for ClientError {
+    fn from(err: bson::ser::Error) -> Self {
+        ClientError::Bson(err.to_string())
+    }
+}
+
+impl From<bson::de::Error> for ClientError {
+    fn from(err: bson::de::Error) -> Self {
+        ClientError::Bson(err.to_string())
+    }
+}
@@ -0,0 +1,337 @@
+//! Async client for the rusdb gRPC server.
+//!
+//! `Client::connect` opens a small pool of connections and `Client::collection`
+//! hands out typed collections whose documents are (de)serialized with serde.
+//! The generated stubs live in `proto` for calls without a typed wrapper,
+//! such as `Watch`; `Client::request` adds the credentials to those.
+
+// tonic::Status is large by design and is carried by ClientError.
+#![allow(clippy::result_large_err)]
+
+mod collection;
+mod error;
+mod query;
+
+pub mod proto {
+    tonic::include_proto!("grpc");
+}
+
+pub use collection::{Collection, FindOptions};
+pub use error::ClientError;
+pub use query::{Filter, Update};
+
+use proto::auth_client::AuthClient;
+use proto::rus_db_client::RusDbClient;
+use proto::AuthenticateRequest;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+use tonic::{Code, Request, Response, Status};
+use tower::service_fn;
+
+/// Session tokens are renewed this long before they expire.
+const TOKEN_MARGIN: Duration = Duration::from_secs(30);
+
+/// How calls failing with a transient status code are retried. Only calls
+/// that are safe to repeat are retried unless `retry_writes` is set: reads,
+/// inserts where every document has an `_id`, and removes without a limit.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts per call, the first one included. `1` disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    /// The backoff doubles after every attempt up to this limit.
+    pub max_backoff: Duration,
+    /// Also retry writes that may be applied twice, such as updates or
+    /// inserts of documents without an `_id`.
+    pub retry_writes: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_writes: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `UNAVAILABLE` and `ABORTED` may succeed when repeated. A write can
+    /// still have been applied when the connection dropped before the
+    /// response arrived.
+    pub fn is_transient(&self, code: Code) -> bool {
+        matches!(code, Code::Unavailable | Code::Aborted)
+    }
+}
+
+/// Certificates for connecting over TLS. Paths point to PEM files.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// CA the server certificate must chain to, instead of the system roots.
+    pub ca_cert: Option<PathBuf>,
+    /// Name to verify the server certificate against, if not the url host.
+    pub domain: Option<String>,
+    /// Client certificate and key for servers requiring mutual TLS.
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// Exchanged for a session token, which is renewed as it expires.
+    Password { username: String, password: String },
+    /// A session token obtained elsewhere.
+    Token(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Server address, `https://` when `tls` is set, or `unix:<path>` for
+    /// a server listening on a Unix domain socket.
+    pub url: String,
+    /// Number of connections calls are spread over.
+    pub pool_size: usize,
+    pub connect_timeout: Option<Duration>,
+    /// Deadline of every call.
+    pub timeout: Option<Duration>,
+    pub tls: Option<TlsOptions>,
+    pub credentials: Option<Credentials>,
+    pub retry: RetryPolicy,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8009".to_string(),
+            pool_size: 4,
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: None,
+            tls: None,
+            credentials: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+struct Inner {
+    channels: Vec<Channel>,
+    next: AtomicUsize,
+    credentials: Option<Credentials>,
+    token: Mutex<Option<(String, SystemTime)>>,
+    retry: RetryPolicy,
+}
+
+/// A connection pool to one server. Cloning is cheap and shares the pool.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, ClientError> {
+    std::fs::read(path).map_err(|source| ClientError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn tls_config(tls: &TlsOptions) -> Result<ClientTlsConfig, ClientError> {
+    let mut config = ClientTlsConfig::new();
+    if let Some(path) = &tls.ca_cert {
+        config = config.ca_certificate(Certificate::from_pem(read_pem(path)?));
+    }
+    if let Some(domain) = &tls.domain {
+        config = config.domain_name(domain.clone());
+    }
+    match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => {
+            config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        (None, None) => {}
+        _ => {
+            return Err(ClientError::Config(
+                "a client certificate needs both cert and key".to_string(),
+            ))
+        }
+    }
+    Ok(config)
+}
+
+impl Client {
+    /// Connects to the server with default options.
+    pub async fn connect_to(url: impl Into<String>) -> Result<Self, ClientError> {
+        Self::connect(ClientOptions {
+            url: url.into(),
+            ..Default::default()
+        })
+        .await
+    }
+    /// Opens every connection of the pool, failing if the server cannot be
+    /// reached.
+    pub async fn connect(options: ClientOptions) -> Result<Self, ClientError> {
+        let socket = options.url.strip_prefix("unix:").map(PathBuf::from);
+        // The authority is only used for the HTTP/2 headers on a socket.
+        let url = match &socket {
+            Some(_) => "http://localhost".to_string(),
+            None => options.url.clone(),
+        };
+        let mut endpoint = Endpoint::from_shared(url)
+            .map_err(|e| ClientError::Config(format!("invalid url {}: {}", options.url, e)))?;
+        if let Some(timeout) = options.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = options.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(tls) = &options.tls {
+            endpoint = endpoint.tls_config(tls_config(tls)?)?;
+        }
+        let mut channels = Vec::with_capacity(options.pool_size.max(1));
+        for _ in 0..options.pool_size.max(1) {
+            let channel = match &socket {
+                Some(path) => {
+                    let path = path.clone();
+                    endpoint
+                        .connect_with_connector(service_fn(move |_: Uri| {
+                            UnixStream::connect(path.clone())
+                        }))
+                        .await?
+                }
+                None => endpoint.connect().await?,
+            };
+            channels.push(channel);
+        }
+        Ok(Self {
+            inner: Arc::new(Inner {
+                channels,
+                next: AtomicUsize::new(0),
+                credentials: options.credentials,
+                token: Mutex::new(None),
+                retry: options.retry,
+            }),
+        })
+    }
+    /// A typed handle on `database.name`. An empty database name selects the
+    /// server's default database.
+    pub fn collection<T>(&self, database: &str, name: &str) -> Collection<T> {
+        Collection::new(self.clone(), database, name)
+    }
+    /// The next connection of the pool, for use with the `proto` clients.
+    pub fn channel(&self) -> Channel {
+        let next = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        self.inner.channels[next % self.inner.channels.len()].clone()
+    }
+    /// Wraps a message in a request carrying the session token, if any.
+    pub async fn request<T>(&self, message: T) -> Result<Request<T>, ClientError> {
+        let token = self.token(false).await?;
+        Ok(authorized(message, token.as_deref())?)
+    }
+    /// Returns the session token, authenticating first if there is none
+    /// or it is about to expire. `renew` forces a new one.
+    async fn token(&self, renew: bool) -> Result<Option<String>, ClientError> {
+        let (username, password) = match &self.inner.credentials {
+            None => return Ok(None),
+            Some(Credentials::Token(token)) => return Ok(Some(token.clone())),
+            Some(Credentials::Password { username, password }) => (username, password),
+        };
+        let mut cached = self.inner.token.lock().await;
+        if let Some((token, expires)) = &*cached {
+            if !renew && SystemTime::now() + TOKEN_MARGIN < *expires {
+                return Ok(Some(token.clone()));
+            }
+        }
+        let request = AuthenticateRequest {
+            username: username.clone(),
+            password: password.clone(),
+        };
+        let response = self
+            .retry(true, |channel| {
+                let request = request.clone();
+                async move { AuthClient::new(channel).authenticate(request).await }
+            })
+            .await?
+            .into_inner();
+        let expires = UNIX_EPOCH + Duration::from_millis(response.expires.max(0) as u64);
+        *cached = Some((response.token.clone(), expires));
+        Ok(Some(response.token))
+    }
+    /// Runs `call` on the pool, retrying transient failures with backoff
+    /// when it is `idempotent` or the policy allows retrying writes.
+    async fn retry<R, F, Fut>(&self, idempotent: bool, mut call: F) -> Result<R, Status>
+    where
+        F: FnMut(Channel) -> Fut,
+        Fut: Future<Output = Result<R, Status>>,
+    {
+        let policy = &self.inner.retry;
+        let max_attempts = if idempotent || policy.retry_writes {
+            policy.max_attempts
+        } else {
+            1
+        };
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match call(self.channel()).await {
+                Err(status) if attempt < max_attempts && policy.is_transient(status.code()) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(policy.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Sends `message` through `call` with credentials, retrying it if it
+    /// is `idempotent`. A rejected session token is renewed once.
+    pub(crate) async fn call<M, R, F, Fut>(
+        &self,
+        message: M,
+        idempotent: bool,
+        call: F,
+    ) -> Result<R, ClientError>
+    where
+        M: Clone,
+        F: Fn(RusDbClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let mut renewed = false;
+        loop {
+            let token = self.token(renewed).await?;
+            let result = self
+                .retry(idempotent, |channel| {
+                    let request = authorized(message.clone(), token.as_deref());
+                    let call = &call;
+                    async move { call(RusDbClient::new(channel), request?).await }
+                })
+                .await;
+            match result {
+                Err(status)
+                    if status.code() == Code::Unauthenticated
+                        && !renewed
+                        && matches!(self.inner.credentials, Some(Credentials::Password { .. })) =>
+                {
+                    renewed = true;
+                }
+                result => return Ok(result?.into_inner()),
+            }
+        }
+    }
+}
+
+fn authorized<T>(message: T, token: Option<&str>) -> Result<Request<T>, Status> {
+    let mut request = Request::new(message);
+    if let Some(token) = token {
+        let value = MetadataValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| Status::invalid_argument("Session token is not valid metadata."))?;
+        request.metadata_mut().insert("authorization", value);
+    }
+    Ok(request)
+}
@@ -0,0 +1,102 @@
+//! Builders for the BSON `filter` and `updates` fields of requests.
+
+use bson::{Bson, Document};
+
+/// Matches documents whose fields equal every value given to `eq`. An empty
+/// filter matches everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter(Document);
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Only matches documents whose `field` equals `value`.
+    pub fn eq(mut self, field: impl Into<String>, value: impl Into<Bson>) -> Self {
+        self.0.insert(field, value);
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn document(&self) -> &Document {
+        &self.0
+    }
+    /// The encoded filter, as carried in the `filter` field. A value that
+    /// cannot be encoded is an error rather than empty bytes, which would
+    /// be sent as a filter matching everything.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, bson::ser::Error> {
+        bson::to_vec(&self.0)
+    }
+}
+
+impl From<Document> for Filter {
+    fn from(doc: Document) -> Self {
+        Self(doc)
+    }
+}
+
+/// Fields to overwrite on matching documents. `_id` is never changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Update(Document);
+
+impl Update {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set(mut self, field: impl Into<String>, value: impl Into<Bson>) -> Self {
+        self.0.insert(field, value);
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn document(&self) -> &Document {
+        &self.0
+    }
+    /// The encoded update, as carried in the `updates` field.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, bson::ser::Error> {
+        bson::to_vec(&self.0)
+    }
+}
+
+impl From<Document> for Update {
+    fn from(doc: Document) -> Self {
+        Self(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn filter_bytes_decode_to_its_fields() {
+        let filter = Filter::new().eq("name", "ada").eq("age", 36);
+        let decoded: Document = bson::from_slice(&filter.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, doc! { "name": "ada", "age": 36 });
+        assert!(!filter.is_empty());
+    }
+
+    #[test]
+    fn empty_builders_encode_an_empty_document() {
+        let empty = bson::to_vec(&doc! {}).unwrap();
+        assert!(Filter::new().is_empty());
+        assert_eq!(Filter::new().to_bytes().unwrap(), empty);
+        assert_eq!(Update::new().to_bytes().unwrap(), empty);
+    }
+
+    #[test]
+    fn update_bytes_decode_to_its_fields() {
+        let update = Update::from(doc! { "age": 37 }).set("name", "ada");
+        let decoded: Document = bson::from_slice(&update.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, doc! { "age": 37, "name": "ada" });
+    }
+
+    #[test]
+    fn unencodable_fields_are_errors() {
+        assert!(Filter::new().eq("a\0b", 1).to_bytes().is_err());
+        assert!(Update::new().set("a\0b", 1).to_bytes().is_err());
+    }
+}
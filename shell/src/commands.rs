@@ -0,0 +1,812 @@
+//! Parses shell commands and runs them as RPCs.
+
+use bson::{doc, Bson, Document};
+use rusdb_client::proto::rus_db_client::RusDbClient;
+use rusdb_client::proto::*;
+use rusdb_client::{Client, ClientError, ClientOptions, Credentials};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+use std::error::Error;
+use tonic::transport::Channel;
+use tonic::{Code, Response, Status, Streaming};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+pub const HELP: &str = "\
+Documents and filters are MongoDB Extended JSON, e.g. {\"_id\": {\"$oid\": \"...\"}}.
+
+  use <database>
+  insert <collection> <document | [documents]> [ordered] [atomic]
+  find <collection> [filter] [limit <n>] [reverse]
+  get <collection> <id>
+  update <collection> <filter> <updates> [limit <n>]
+  replace <collection> <filter> <document>
+  remove <collection> <filter> [limit <n>]
+  bulk-write <[operations]> [ordered]
+      {\"collection\": c, \"insert\": doc}
+      {\"collection\": c, \"update\": {\"filter\": f, \"updates\": u, \"limit\": n}}
+      {\"collection\": c, \"replace\": {\"filter\": f, \"document\": doc}}
+      {\"collection\": c, \"remove\": {\"filter\": f, \"limit\": n}}
+  watch [collection] [after <seq>]        Ctrl-C stops
+  tail <collection> [filter]              Ctrl-C stops
+  list-databases
+  drop-database <database>
+  list-collections
+  create-collection <collection> [options] [if-not-exists]
+  drop-collection <collection>
+  rename-collection <collection> <to>
+  collection-stats <collection>
+  create-user <username> <password> [roles...]
+  drop-user <username>
+  create-role <name> <[{\"privilege\": \"read\", \"database\": \"*\", \"collection\": \"*\"}]>
+  drop-role <name>
+  grant-role <username> <role>
+  revoke-role <username> <role>
+  reload-config
+  auth <username> <password>
+  help
+  exit";
+
+/// Every command, for completion.
+pub const COMMANDS: &[&str] = &[
+    "use",
+    "insert",
+    "find",
+    "get",
+    "update",
+    "replace",
+    "remove",
+    "bulk-write",
+    "watch",
+    "tail",
+    "list-databases",
+    "drop-database",
+    "list-collections",
+    "create-collection",
+    "drop-collection",
+    "rename-collection",
+    "collection-stats",
+    "create-user",
+    "drop-user",
+    "create-role",
+    "drop-role",
+    "grant-role",
+    "revoke-role",
+    "reload-config",
+    "auth",
+    "help",
+    "exit",
+];
+
+/// Commands taking a password, which are kept out of the history.
+const SECRET_COMMANDS: &[&str] = &["auth", "create-user"];
+
+/// Whether `line` runs a command taking a password.
+pub fn has_secret(line: &str) -> bool {
+    Args { rest: line }
+        .word()
+        .is_some_and(|command| SECRET_COMMANDS.contains(&command))
+}
+
+/// Whether the shell keeps going after a command.
+#[derive(PartialEq)]
+pub enum Flow {
+    Continue,
+    Exit,
+}
+
+/// The words and JSON values of a command line, consumed in order.
+struct Args<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Args<'a> {
+    fn word(&mut self) -> Option<&'a str> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(&rest[..end])
+    }
+    fn required(&mut self, what: &str) -> Result<&'a str> {
+        self.word()
+            .ok_or_else(|| format!("Missing {}.", what).into())
+    }
+    /// A JSON value, or a bare word taken as a string.
+    fn json(&mut self) -> Result<Option<Value>> {
+        let rest = self.rest.trim_start();
+        if !rest.starts_with(['{', '[', '"']) {
+            return Ok(self
+                .word()
+                .map(|word| serde_json::from_str(word).unwrap_or_else(|_| json!(word))));
+        }
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        let value = values.next().transpose()?;
+        self.rest = &rest[values.byte_offset()..];
+        Ok(value)
+    }
+    fn document(&mut self, what: &str) -> Result<Document> {
+        match self.json()? {
+            Some(value) => to_document(value, what),
+            None => Err(format!("Missing {}.", what).into()),
+        }
+    }
+    /// A filter, or the empty one when the line ends or a keyword follows.
+    fn filter(&mut self) -> Result<Document> {
+        if self.rest.trim_start().starts_with('{') {
+            self.document("filter")
+        } else {
+            Ok(Document::new())
+        }
+    }
+    /// Trailing keywords, each in `allowed`. `limit` takes a number.
+    fn flags(&mut self, allowed: &[&str]) -> Result<Flags> {
+        let mut flags = Flags::default();
+        while let Some(word) = self.word() {
+            if !allowed.contains(&word) {
+                return Err(format!("Unexpected {}.", word).into());
+            }
+            match word {
+                "limit" => flags.limit = Some(self.required("limit")?.parse()?),
+                "reverse" => flags.reverse = true,
+                "ordered" => flags.ordered = true,
+                "atomic" => flags.atomic = true,
+                "if-not-exists" => flags.if_not_exists = true,
+                _ => {}
+            }
+        }
+        Ok(flags)
+    }
+    fn finish(&mut self) -> Result<()> {
+        match self.word() {
+            Some(word) => Err(format!("Unexpected {}.", word).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Flags {
+    limit: Option<u32>,
+    reverse: bool,
+    ordered: bool,
+    atomic: bool,
+    if_not_exists: bool,
+}
+
+fn to_document(value: Value, what: &str) -> Result<Document> {
+    match value {
+        Value::Object(map) => Ok(Document::try_from(map)?),
+        _ => Err(format!("The {} must be a JSON object.", what).into()),
+    }
+}
+
+fn to_bytes(doc: &Document) -> Result<Vec<u8>> {
+    Ok(bson::to_vec(doc)?)
+}
+
+/// Relaxed Extended JSON of an encoded document.
+fn to_json(data: &[u8]) -> Result<Value> {
+    let doc: Document = bson::from_slice(data)?;
+    Ok(Bson::Document(doc).into_relaxed_extjson())
+}
+
+fn print(value: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+fn rpc<T>(result: std::result::Result<Response<T>, Status>) -> Result<T> {
+    Ok(result.map_err(ClientError::from)?.into_inner())
+}
+
+/// Parses `{"flush_time", "validator", "validation_level", "ttl",
+/// "capped", "id_strategy"}` into collection options.
+fn collection_options(value: Value) -> Result<CollectionOptions> {
+    let map = match value {
+        Value::Object(map) => map,
+        _ => return Err("Collection options must be a JSON object.".into()),
+    };
+    let mut options = CollectionOptions::default();
+    for (key, value) in map {
+        match key.as_str() {
+            "flush_time" => {
+                options.flush_time =
+                    Some(value.as_u64().ok_or("flush_time must be a number.")? as u32)
+            }
+            "validator" => options.validator = Some(to_bytes(&to_document(value, "validator")?)?),
+            "validation_level" => {
+                let level = match value.as_str() {
+                    Some("strict") => ValidationLevel::Strict,
+                    Some("warn") => ValidationLevel::Warn,
+                    _ => return Err("validation_level must be strict or warn.".into()),
+                };
+                options.validation_level = Some(level as i32);
+            }
+            "ttl" => {
+                options.ttl = Some(TtlOptions {
+                    field: value["field"]
+                        .as_str()
+                        .ok_or("ttl needs a field.")?
+                        .to_string(),
+                    expire_after_seconds: value["expire_after_seconds"]
+                        .as_u64()
+                        .ok_or("ttl needs expire_after_seconds.")?,
+                })
+            }
+            "capped" => {
+                options.capped = Some(CappedOptions {
+                    max_documents: value["max_documents"].as_u64(),
+                    max_size: value["max_size"].as_u64(),
+                })
+            }
+            "id_strategy" => {
+                let strategy = match value.as_str() {
+                    Some("uuid-v4") => IdStrategy::UuidV4,
+                    Some("uuid-v7") => IdStrategy::UuidV7,
+                    Some("ulid") => IdStrategy::Ulid,
+                    Some("object-id") => IdStrategy::ObjectId,
+                    Some("client") => IdStrategy::Client,
+                    _ => {
+                        return Err(
+                            "id_strategy must be uuid-v4, uuid-v7, ulid, object-id or client."
+                                .into(),
+                        )
+                    }
+                };
+                options.id_strategy = Some(strategy as i32);
+            }
+            other => return Err(format!("Unknown collection option {}.", other).into()),
+        }
+    }
+    Ok(options)
+}
+
+fn options_to_json(options: &CollectionOptions) -> Result<Value> {
+    let mut map = Map::new();
+    if let Some(flush_time) = options.flush_time {
+        map.insert("flush_time".into(), json!(flush_time));
+    }
+    if let Some(validator) = &options.validator {
+        map.insert("validator".into(), to_json(validator)?);
+    }
+    if let Some(level) = options.validation_level.and_then(ValidationLevel::from_i32) {
+        let level = match level {
+            ValidationLevel::Strict => "strict",
+            ValidationLevel::Warn => "warn",
+        };
+        map.insert("validation_level".into(), json!(level));
+    }
+    if let Some(ttl) = &options.ttl {
+        map.insert(
+            "ttl".into(),
+            json!({"field": ttl.field, "expire_after_seconds": ttl.expire_after_seconds}),
+        );
+    }
+    if let Some(capped) = &options.capped {
+        map.insert(
+            "capped".into(),
+            json!({"max_documents": capped.max_documents, "max_size": capped.max_size}),
+        );
+    }
+    if let Some(strategy) = options.id_strategy.and_then(IdStrategy::from_i32) {
+        let strategy = match strategy {
+            IdStrategy::UuidV4 => "uuid-v4",
+            IdStrategy::UuidV7 => "uuid-v7",
+            IdStrategy::Ulid => "ulid",
+            IdStrategy::ObjectId => "object-id",
+            IdStrategy::Client => "client",
+        };
+        map.insert("id_strategy".into(), json!(strategy));
+    }
+    Ok(Value::Object(map))
+}
+
+/// One entry of a `bulk-write` array.
+fn write_operation(value: Value, database: &str) -> Result<WriteOperation> {
+    let mut map = match value {
+        Value::Object(map) => map,
+        _ => return Err("Every operation must be a JSON object.".into()),
+    };
+    let collection = match map.remove("collection") {
+        Some(Value::String(name)) => name,
+        _ => return Err("Every operation needs a collection.".into()),
+    };
+    let database = match map.remove("database") {
+        Some(Value::String(name)) => name,
+        _ => database.to_string(),
+    };
+    let (kind, body) = match map.into_iter().next() {
+        Some(entry) => entry,
+        None => return Err("Operation is empty.".into()),
+    };
+    let document = |body: &Value, key: &str| -> Result<Vec<u8>> {
+        match body.get(key) {
+            Some(value) => to_bytes(&to_document(value.clone(), key)?),
+            None => to_bytes(&Document::new()),
+        }
+    };
+    let limit = body.get("limit").and_then(Value::as_u64).map(|n| n as u32);
+    let op = match kind.as_str() {
+        "insert" => write_operation::Op::Insert(BulkInsert {
+            document: to_bytes(&to_document(body, "document")?)?,
+        }),
+        "update" => write_operation::Op::Update(BulkUpdate {
+            filter: document(&body, "filter")?,
+            updates: document(&body, "updates")?,
+            limit,
+        }),
+        "replace" => write_operation::Op::Replace(BulkReplace {
+            filter: document(&body, "filter")?,
+            document: document(&body, "document")?,
+        }),
+        "remove" => write_operation::Op::Remove(BulkRemove {
+            filter: document(&body, "filter")?,
+            limit,
+        }),
+        other => return Err(format!("Unknown operation {}.", other).into()),
+    };
+    Ok(WriteOperation {
+        collection,
+        database,
+        op: Some(op),
+    })
+}
+
+pub struct Shell {
+    client: Client,
+    options: ClientOptions,
+    pub database: String,
+}
+
+impl Shell {
+    pub fn new(client: Client, options: ClientOptions, database: String) -> Self {
+        Self {
+            client,
+            options,
+            database,
+        }
+    }
+    fn stub(&self) -> RusDbClient<Channel> {
+        RusDbClient::new(self.client.channel())
+    }
+    /// Collections of the current database, for completion. Empty when
+    /// they cannot be listed.
+    pub async fn collections(&self) -> Vec<String> {
+        let request = ListCollectionsRequest {
+            database: self.database.clone(),
+        };
+        match self.client.request(request).await {
+            Ok(request) => self
+                .stub()
+                .list_collections(request)
+                .await
+                .map(|response| response.into_inner().collections)
+                .unwrap_or_default(),
+            Err(_) => vec![],
+        }
+    }
+    /// Prints every message of a stream until it ends or Ctrl-C is pressed.
+    async fn follow<T>(
+        &self,
+        mut stream: Streaming<T>,
+        show: impl Fn(T) -> Result<Value>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                message = stream.message() => match message.map_err(ClientError::from)? {
+                    Some(message) => print(&show(message)?),
+                    None => return Ok(()),
+                },
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+    pub async fn run(&mut self, line: &str) -> Result<Flow> {
+        let mut args = Args { rest: line };
+        let command = match args.word() {
+            Some(command) => command,
+            None => return Ok(Flow::Continue),
+        };
+        let database = self.database.clone();
+        match command {
+            "exit" | "quit" => return Ok(Flow::Exit),
+            "help" => println!("{}", HELP),
+            "use" => {
+                let name = args.required("database")?;
+                args.finish()?;
+                self.database = name.to_string();
+            }
+            "auth" => {
+                let username = args.required("username")?.to_string();
+                let password = args.required("password")?.to_string();
+                args.finish()?;
+                let mut options = self.options.clone();
+                options.credentials = Some(Credentials::Password { username, password });
+                let client = Client::connect(options.clone()).await?;
+                // Authenticates up front so bad credentials fail here.
+                client.request(()).await?;
+                self.client = client;
+                self.options = options;
+                println!("Authenticated.");
+            }
+            "insert" => {
+                let collection = args.required("collection")?.to_string();
+                let documents = match args.json()? {
+                    Some(Value::Array(values)) => values
+                        .into_iter()
+                        .map(|value| to_bytes(&to_document(value, "document")?))
+                        .collect::<Result<Vec<_>>>()?,
+                    Some(value) => vec![to_bytes(&to_document(value, "document")?)?],
+                    None => return Err("Missing document.".into()),
+                };
+                let flags = args.flags(&["ordered", "atomic"])?;
+                let request = self.client.request(InsertRequest {
+                    collection,
+                    database,
+                    documents,
+                    return_old: false,
+                    ordered: flags.ordered,
+                    atomic: flags.atomic,
+                });
+                let response = rpc(self.stub().insert(request.await?).await)?;
+                let mut ids = vec![];
+                let mut errors = vec![];
+                for insert in response.inserts {
+                    match insert.error {
+                        Some(error) => errors.push(json!({
+                            "index": insert.index,
+                            "code": format!("{:?}", Code::from_i32(error.code)),
+                            "message": error.message,
+                        })),
+                        None => ids.push(insert.id),
+                    }
+                }
+                let mut result = json!({"inserted": response.count, "ids": ids});
+                if !errors.is_empty() {
+                    result["errors"] = json!(errors);
+                }
+                print(&result);
+            }
+            "find" => {
+                let collection = args.required("collection")?.to_string();
+                let filter = args.filter()?;
+                let flags = args.flags(&["limit", "reverse"])?;
+                let request = self.client.request(FindRequest {
+                    collection,
+                    database,
+                    filter: Some(to_bytes(&filter)?),
+                    limit: flags.limit,
+                    reverse: flags.reverse,
+                });
+                let response = rpc(self.stub().find(request.await?).await)?;
+                for document in &response.documents {
+                    print(&to_json(document)?);
+                }
+                println!("{} document(s)", response.count);
+            }
+            "get" => {
+                let collection = args.required("collection")?.to_string();
+                let id = args.json()?.ok_or("Missing id.")?;
+                args.finish()?;
+                // Text ids let the server try every id type; anything else is
+                // looked up exactly.
+                let (id, id_document) = match id {
+                    Value::String(id) => (id, None),
+                    other => (
+                        String::new(),
+                        Some(to_bytes(&doc! { "_id": Bson::try_from(other)? })?),
+                    ),
+                };
+                let request = self.client.request(GetRequest {
+                    collection,
+                    database,
+                    id,
+                    id_document,
+                });
+                match rpc(self.stub().get(request.await?).await)?.document {
+                    Some(document) => print(&to_json(&document)?),
+                    None => println!("null"),
+                }
+            }
+            "update" => {
+                let collection = args.required("collection")?.to_string();
+                let filter = args.document("filter")?;
+                let updates = args.document("updates")?;
+                let flags = args.flags(&["limit"])?;
+                let request = self.client.request(UpdateRequest {
+                    collection,
+                    database,
+                    filter: to_bytes(&filter)?,
+                    updates: to_bytes(&updates)?,
+                    limit: flags.limit,
+                });
+                let response = rpc(self.stub().update(request.await?).await)?;
+                for document in &response.updated {
+                    print(&to_json(document)?);
+                }
+                println!("{} document(s) updated", response.count);
+            }
+            "replace" => {
+                let collection = args.required("collection")?.to_string();
+                let filter = args.document("filter")?;
+                let document = args.document("document")?;
+                args.finish()?;
+                let request = self.client.request(ReplaceRequest {
+                    collection,
+                    database,
+                    filter: to_bytes(&filter)?,
+                    document: to_bytes(&document)?,
+                });
+                match rpc(self.stub().replace(request.await?).await)?.document {
+                    Some(document) => print(&to_json(&document)?),
+                    None => println!("0 documents replaced"),
+                }
+            }
+            "remove" => {
+                let collection = args.required("collection")?.to_string();
+                let filter = args.document("filter")?;
+                let flags = args.flags(&["limit"])?;
+                let request = self.client.request(RemoveRequest {
+                    collection,
+                    database,
+                    filter: to_bytes(&filter)?,
+                    limit: flags.limit,
+                });
+                let response = rpc(self.stub().remove(request.await?).await)?;
+                println!("{} document(s) removed", response.count);
+            }
+            "bulk-write" => {
+                let operations = match args.json()? {
+                    Some(Value::Array(values)) => values
+                        .into_iter()
+                        .map(|value| write_operation(value, &database))
+                        .collect::<Result<Vec<_>>>()?,
+                    _ => return Err("Operations must be a JSON array.".into()),
+                };
+                let flags = args.flags(&["ordered"])?;
+                let request = self.client.request(BulkWriteRequest {
+                    operations,
+                    ordered: flags.ordered,
+                });
+                let response = rpc(self.stub().bulk_write(request.await?).await)?;
+                let errors: Vec<Value> = response
+                    .errors
+                    .iter()
+                    .map(|error| {
+                        json!({
+                            "index": error.index,
+                            "code": format!("{:?}", Code::from_i32(error.code)),
+                            "message": error.message,
+                        })
+                    })
+                    .collect();
+                print(&json!({
+                    "inserted": response.inserted,
+                    "updated": response.updated,
+                    "replaced": response.replaced,
+                    "removed": response.removed,
+                    "inserted_ids": response.inserted_ids,
+                    "errors": errors,
+                }));
+            }
+            "watch" => {
+                let mut collection = None;
+                let mut after = None;
+                while let Some(word) = args.word() {
+                    match word {
+                        "after" => after = Some(args.required("sequence")?.parse()?),
+                        name if collection.is_none() => collection = Some(name.to_string()),
+                        other => return Err(format!("Unexpected {}.", other).into()),
+                    }
+                }
+                let request = self.client.request(WatchRequest {
+                    collection,
+                    resume_after: after,
+                    database: Some(database),
+                });
+                let stream = rpc(self.stub().watch(request.await?).await)?;
+                self.follow(stream, |event| {
+                    let op = match Operation::from_i32(event.op) {
+                        Some(Operation::Insert) => "insert",
+                        Some(Operation::Update) => "update",
+                        Some(Operation::Remove) => "remove",
+                        None => "unknown",
+                    };
+                    let document = match &event.document {
+                        Some(document) => to_json(document)?,
+                        None => Value::Null,
+                    };
+                    Ok(json!({
+                        "seq": event.seq,
+                        "op": op,
+                        "database": event.database,
+                        "collection": event.collection,
+                        "_id": event.id,
+                        "document": document,
+                        "timestamp": event.timestamp,
+                    }))
+                })
+                .await?;
+            }
+            "tail" => {
+                let collection = args.required("collection")?.to_string();
+                let filter = args.filter()?;
+                args.finish()?;
+                let request = self.client.request(TailRequest {
+                    collection,
+                    database,
+                    filter: Some(to_bytes(&filter)?),
+                });
+                let stream = rpc(self.stub().tail(request.await?).await)?;
+                self.follow(stream, |response| to_json(&response.document))
+                    .await?;
+            }
+            "list-databases" => {
+                args.finish()?;
+                let request = self.client.request(ListDatabasesRequest {});
+                for name in rpc(self.stub().list_databases(request.await?).await)?.databases {
+                    println!("{}", name);
+                }
+            }
+            "drop-database" => {
+                let database = args.required("database")?.to_string();
+                args.finish()?;
+                let request = self.client.request(DropDatabaseRequest { database });
+                let response = rpc(self.stub().drop_database(request.await?).await)?;
+                print(&json!({"dropped": response.dropped}));
+            }
+            "list-collections" => {
+                args.finish()?;
+                for name in self.collections().await {
+                    println!("{}", name);
+                }
+            }
+            "create-collection" => {
+                let collection = args.required("collection")?.to_string();
+                let options = if args.rest.trim_start().starts_with('{') {
+                    Some(collection_options(args.json()?.unwrap_or_default())?)
+                } else {
+                    None
+                };
+                let flags = args.flags(&["if-not-exists"])?;
+                let request = self.client.request(CreateCollectionRequest {
+                    collection,
+                    database,
+                    options,
+                    if_not_exists: flags.if_not_exists,
+                });
+                let response = rpc(self.stub().create_collection(request.await?).await)?;
+                print(&json!({"created": response.created}));
+            }
+            "drop-collection" => {
+                let collection = args.required("collection")?.to_string();
+                args.finish()?;
+                let request = self.client.request(DropCollectionRequest {
+                    collection,
+                    database,
+                });
+                let response = rpc(self.stub().drop_collection(request.await?).await)?;
+                print(&json!({"dropped": response.dropped}));
+            }
+            "rename-collection" => {
+                let collection = args.required("collection")?.to_string();
+                let to = args.required("new name")?.to_string();
+                args.finish()?;
+                let request = self.client.request(RenameCollectionRequest {
+                    collection,
+                    to,
+                    database,
+                });
+                rpc(self.stub().rename_collection(request.await?).await)?;
+                println!("Renamed.");
+            }
+            "collection-stats" => {
+                let collection = args.required("collection")?.to_string();
+                args.finish()?;
+                let request = self.client.request(CollectionStatsRequest {
+                    collection,
+                    database,
+                });
+                let stats = rpc(self.stub().collection_stats(request.await?).await)?;
+                let options = match &stats.options {
+                    Some(options) => options_to_json(options)?,
+                    None => Value::Null,
+                };
+                print(&json!({
+                    "database": stats.database,
+                    "collection": stats.collection,
+                    "count": stats.count,
+                    "memory_size": stats.memory_size,
+                    "disk_size": stats.disk_size,
+                    "cached": stats.cached,
+                    "last_access": stats.last_access,
+                    "options": options,
+                }));
+            }
+            "create-user" => {
+                let username = args.required("username")?.to_string();
+                let password = args.required("password")?.to_string();
+                let mut roles = vec![];
+                while let Some(role) = args.word() {
+                    roles.push(role.to_string());
+                }
+                let request = self.client.request(CreateUserRequest {
+                    username,
+                    password,
+                    roles,
+                });
+                let response = rpc(self.stub().create_user(request.await?).await)?;
+                print(&json!({"_id": response.id}));
+            }
+            "drop-user" => {
+                let username = args.required("username")?.to_string();
+                args.finish()?;
+                let request = self.client.request(DropUserRequest { username });
+                let response = rpc(self.stub().drop_user(request.await?).await)?;
+                print(&json!({"dropped": response.dropped}));
+            }
+            "create-role" => {
+                let name = args.required("name")?.to_string();
+                let grants = match args.json()? {
+                    Some(Value::Array(grants)) => grants,
+                    _ => return Err("Privileges must be a JSON array.".into()),
+                };
+                args.finish()?;
+                let mut privileges = vec![];
+                for grant in grants {
+                    let privilege = match grant["privilege"].as_str() {
+                        Some("read") => Privilege::Read,
+                        Some("write") => Privilege::Write,
+                        Some("admin") => Privilege::Admin,
+                        _ => return Err("privilege must be read, write or admin.".into()),
+                    };
+                    let pattern = |key: &str| grant[key].as_str().unwrap_or("").to_string();
+                    privileges.push(PrivilegeGrant {
+                        privilege: privilege as i32,
+                        database: pattern("database"),
+                        collection: pattern("collection"),
+                    });
+                }
+                let request = self.client.request(CreateRoleRequest { name, privileges });
+                rpc(self.stub().create_role(request.await?).await)?;
+                println!("Created.");
+            }
+            "drop-role" => {
+                let name = args.required("name")?.to_string();
+                args.finish()?;
+                let request = self.client.request(DropRoleRequest { name });
+                let response = rpc(self.stub().drop_role(request.await?).await)?;
+                print(&json!({"dropped": response.dropped}));
+            }
+            "grant-role" | "revoke-role" => {
+                let username = args.required("username")?.to_string();
+                let role = args.required("role")?.to_string();
+                args.finish()?;
+                let changed = if command == "grant-role" {
+                    let request = self.client.request(GrantRoleRequest { username, role });
+                    rpc(self.stub().grant_role(request.await?).await)?.changed
+                } else {
+                    let request = self.client.request(RevokeRoleRequest { username, role });
+                    rpc(self.stub().revoke_role(request.await?).await)?.changed
+                };
+                print(&json!({"changed": changed}));
+            }
+            "reload-config" => {
+                args.finish()?;
+                let request = self.client.request(ReloadConfigRequest {});
+                let response = rpc(self.stub().reload_config(request.await?).await)?;
+                print(&json!({
+                    "applied": response.applied,
+                    "restart_required": response.restart_required,
+                }));
+            }
+            other => return Err(format!("Unknown command {}, try help.", other).into()),
+        }
+        Ok(Flow::Continue)
+    }
+}
@@ -0,0 +1,286 @@
+//! A small line editor with history and tab completion, for terminals
+//! understanding the usual VT100 escape sequences.
+
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+/// Most history entries kept on disk.
+const HISTORY_SIZE: usize = 1000;
+
+/// What the user did with the line being edited.
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C; the line was discarded.
+    Interrupted,
+    /// Ctrl-D on an empty line.
+    Eof,
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    /// Completions of the first word of a line.
+    pub commands: Vec<String>,
+    /// Completions of every later word.
+    pub words: Vec<String>,
+}
+
+/// Puts the terminal into raw mode until dropped.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(original))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+        }
+    }
+}
+
+pub fn is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+fn read_byte(stdin: &mut io::Stdin) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match stdin.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Longest prefix shared by every candidate.
+fn common_prefix(candidates: &[&String]) -> String {
+    let mut prefix: Vec<char> = candidates[0].chars().collect();
+    for candidate in &candidates[1..] {
+        let shared = prefix
+            .iter()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(shared);
+    }
+    prefix.into_iter().collect()
+}
+
+impl Editor {
+    /// Loads the history kept in `history_path`, if any.
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|data| data.lines().map(|line| line.to_string()).collect())
+            .unwrap_or_default();
+        Self {
+            history,
+            history_path,
+            commands: vec![],
+            words: vec![],
+        }
+    }
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(|l| l.as_str()) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        self.save_history();
+    }
+    /// Drops the history entries matching `secret`, on disk as well.
+    pub fn forget(&mut self, secret: impl Fn(&str) -> bool) {
+        let before = self.history.len();
+        self.history.retain(|line| !secret(line));
+        if self.history.len() != before {
+            self.save_history();
+        }
+    }
+    /// Writes the history readable by the owner only.
+    fn save_history(&self) {
+        if let Some(path) = &self.history_path {
+            let _ = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(path)
+                .and_then(|mut file| {
+                    // The mode only applies to new files.
+                    file.set_permissions(Permissions::from_mode(0o600))?;
+                    file.write_all((self.history.join("\n") + "\n").as_bytes())
+                });
+        }
+    }
+    /// Reads a line from a terminal, or a plain line when stdin is not one.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        if !is_terminal() {
+            let mut line = String::new();
+            return Ok(match io::stdin().read_line(&mut line)? {
+                0 => ReadLine::Eof,
+                _ => ReadLine::Line(line.trim_end_matches(['\r', '\n']).to_string()),
+            });
+        }
+        let _raw = RawMode::enable()?;
+        let mut stdin = io::stdin();
+        let mut line: Vec<char> = vec![];
+        let mut cursor = 0;
+        // Index into the history while browsing it, and the line that was
+        // being typed before browsing started.
+        let mut browsing = self.history.len();
+        let mut draft: Vec<char> = vec![];
+        let mut pending: Vec<u8> = vec![];
+        self.redraw(prompt, &line, cursor)?;
+        loop {
+            let byte = match read_byte(&mut stdin)? {
+                Some(byte) => byte,
+                None => return Ok(ReadLine::Eof),
+            };
+            match byte {
+                b'\r' | b'\n' => {
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(ReadLine::Line(line.into_iter().collect()));
+                }
+                // Ctrl-C
+                3 => {
+                    print!("^C\r\n");
+                    io::stdout().flush()?;
+                    return Ok(ReadLine::Interrupted);
+                }
+                // Ctrl-D
+                4 if line.is_empty() => {
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(ReadLine::Eof);
+                }
+                4 if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                // Ctrl-A and Ctrl-E
+                1 => cursor = 0,
+                5 => cursor = line.len(),
+                // Ctrl-U
+                21 => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                b'\t' => self.complete(&mut line, &mut cursor, prompt)?,
+                127 | 8 if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                27 => {
+                    let kind = read_byte(&mut stdin)?;
+                    let code = read_byte(&mut stdin)?;
+                    match (kind, code) {
+                        (Some(b'['), Some(b'A')) if browsing > 0 => {
+                            if browsing == self.history.len() {
+                                draft = line.clone();
+                            }
+                            browsing -= 1;
+                            line = self.history[browsing].chars().collect();
+                            cursor = line.len();
+                        }
+                        (Some(b'['), Some(b'B')) if browsing < self.history.len() => {
+                            browsing += 1;
+                            line = match self.history.get(browsing) {
+                                Some(entry) => entry.chars().collect(),
+                                None => draft.clone(),
+                            };
+                            cursor = line.len();
+                        }
+                        (Some(b'['), Some(b'C')) if cursor < line.len() => cursor += 1,
+                        (Some(b'['), Some(b'D')) if cursor > 0 => cursor -= 1,
+                        (Some(b'[' | b'O'), Some(b'H')) => cursor = 0,
+                        (Some(b'[' | b'O'), Some(b'F')) => cursor = line.len(),
+                        (Some(b'['), Some(b'3')) => {
+                            // Delete sends ESC [ 3 ~
+                            read_byte(&mut stdin)?;
+                            if cursor < line.len() {
+                                line.remove(cursor);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                byte if byte >= 32 => {
+                    pending.push(byte);
+                    if let Ok(text) = std::str::from_utf8(&pending) {
+                        for c in text.chars() {
+                            line.insert(cursor, c);
+                            cursor += 1;
+                        }
+                        pending.clear();
+                    } else if pending.len() >= 4 {
+                        pending.clear();
+                    }
+                }
+                _ => {}
+            }
+            self.redraw(prompt, &line, cursor)?;
+        }
+    }
+    fn redraw(&self, prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+        let text: String = line.iter().collect();
+        let mut out = io::stdout();
+        write!(out, "\r{}{}\x1b[K", prompt, text)?;
+        if cursor < line.len() {
+            write!(out, "\x1b[{}D", line.len() - cursor)?;
+        }
+        out.flush()
+    }
+    /// Completes the word before the cursor: command names for the first
+    /// word, `words` for the rest. Ambiguous words are extended to the
+    /// longest shared prefix and the candidates listed.
+    fn complete(&self, line: &mut Vec<char>, cursor: &mut usize, prompt: &str) -> io::Result<()> {
+        let start = line[..*cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word: String = line[start..*cursor].iter().collect();
+        let first = line[..start].iter().all(|c| c.is_whitespace());
+        let pool = if first { &self.commands } else { &self.words };
+        let candidates: Vec<&String> = pool.iter().filter(|c| c.starts_with(&word)).collect();
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        let mut completion = common_prefix(&candidates);
+        if candidates.len() == 1 {
+            completion.push(' ');
+        } else if completion == word {
+            let names: Vec<&str> = candidates.iter().map(|c| c.as_str()).collect();
+            print!("\r\n{}\r\n", names.join("  "));
+            self.redraw(prompt, line, *cursor)?;
+            return Ok(());
+        }
+        let added: Vec<char> = completion.chars().skip(word.chars().count()).collect();
+        for c in added {
+            line.insert(*cursor, c);
+            *cursor += 1;
+        }
+        Ok(())
+    }
+}
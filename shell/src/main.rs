@@ -0,0 +1,162 @@
+// tonic::Status is large by design and is carried by ClientError.
+#![allow(clippy::result_large_err)]
+
+mod commands;
+mod editor;
+
+use commands::{has_secret, Flow, Shell, COMMANDS};
+use editor::{Editor, ReadLine};
+use rusdb_client::{Client, ClientOptions, Credentials, TlsOptions};
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+Usage: rusdb-shell [options]
+
+  --url <url>            Server address (default http://127.0.0.1:8009),
+                         or unix:<path> for a Unix domain socket
+  --database <name>      Database to start in (default: the server default)
+  --username <name>      Authenticate as this user
+  --password <password>
+  --token <token>        Use a session token instead of a password
+  --ca-cert <pem>        CA to verify the server with; implies TLS
+  --domain <name>        Name to verify the server certificate against
+  --cert <pem>           Client certificate for mutual TLS
+  --key <pem>            Client key for mutual TLS
+  --eval <commands>      Run newline separated commands and exit
+  --help";
+
+struct Args {
+    options: ClientOptions,
+    database: String,
+    eval: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut options = ClientOptions::default();
+    let mut database = String::new();
+    let mut eval = None;
+    let mut username = None;
+    let mut password = None;
+    let mut tls = TlsOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value.", arg))?;
+        match arg.as_str() {
+            "--url" => options.url = value,
+            "--database" => database = value,
+            "--username" | "-u" => username = Some(value),
+            "--password" | "-p" => password = Some(value),
+            "--token" => options.credentials = Some(Credentials::Token(value)),
+            "--ca-cert" => tls.ca_cert = Some(PathBuf::from(value)),
+            "--domain" => tls.domain = Some(value),
+            "--cert" => tls.cert = Some(PathBuf::from(value)),
+            "--key" => tls.key = Some(PathBuf::from(value)),
+            "--eval" | "-e" => eval = Some(value),
+            _ => return Err(format!("Unknown option {}.\n\n{}", arg, USAGE)),
+        }
+    }
+    match (username, password) {
+        (Some(username), Some(password)) => {
+            options.credentials = Some(Credentials::Password { username, password })
+        }
+        (None, None) => {}
+        _ => return Err("--username and --password go together.".to_string()),
+    }
+    if tls.ca_cert.is_some() || tls.cert.is_some() || tls.key.is_some() {
+        if let Some(rest) = options.url.strip_prefix("http://") {
+            options.url = format!("https://{}", rest);
+        }
+        options.tls = Some(tls);
+    }
+    Ok(Args {
+        options,
+        database,
+        eval,
+    })
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rusdb_shell_history"))
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let client = match Client::connect(args.options.clone()).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Unable to connect to {}: {}", args.options.url, e);
+            std::process::exit(1);
+        }
+    };
+    let mut shell = Shell::new(client, args.options, args.database);
+    if let Some(script) = args.eval {
+        // Scripts stop at the first failing command.
+        for line in script.lines() {
+            match shell.run(line).await {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Exit) => break,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+    let interactive = editor::is_terminal();
+    if interactive {
+        println!("Connected. Type help for a list of commands.");
+    }
+    let mut editor = Editor::new(if interactive { history_path() } else { None });
+    editor.forget(has_secret);
+    editor.commands = COMMANDS.iter().map(|c| c.to_string()).collect();
+    loop {
+        if interactive {
+            editor.words = shell.collections().await;
+        }
+        let prompt = if interactive {
+            let database = if shell.database.is_empty() {
+                "default"
+            } else {
+                shell.database.as_str()
+            };
+            format!("{}> ", database)
+        } else {
+            String::new()
+        };
+        let line = match editor.read_line(&prompt) {
+            Ok(ReadLine::Line(line)) => line,
+            Ok(ReadLine::Interrupted) => continue,
+            Ok(ReadLine::Eof) => break,
+            Err(e) => {
+                eprintln!("Unable to read input: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if !has_secret(&line) {
+            editor.add_history(&line);
+        }
+        match shell.run(&line).await {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => break,
+            Err(e) if interactive => eprintln!("error: {}", e),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+pub mod roles;
+
+use crate::config::AuthConfig;
+use crate::engine::error::RusDbError;
+use crate::engine::ADMIN_DATABASE;
+use crate::tls::subject_name;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use bson::Document;
+use roles::{Privilege, Role, Scope, ROLES_COLLECTION, ROOT_ROLE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use tonic::{Request, Status};
+use uuid::Uuid;
+
+pub const USERS_COLLECTION: &str = "system.users";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+    pub roles: Vec<String>,
+}
+
+/// The authenticated caller, attached to request extensions by the interceptor.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub username: String,
+}
+
+#[derive(Debug)]
+struct Session {
+    identity: Identity,
+    expires: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    UserExists(String),
+    UserNotFound(String),
+    RoleExists(String),
+    RoleNotFound(String),
+    Hash(String),
+    Engine(RusDbError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::UserExists(name) => write!(f, "User {} already exists.", name),
+            AuthError::UserNotFound(name) => write!(f, "User {} does not exist.", name),
+            AuthError::RoleExists(name) => write!(f, "Role {} already exists.", name),
+            AuthError::RoleNotFound(name) => write!(f, "Role {} does not exist.", name),
+            AuthError::Hash(err) => write!(f, "Unable to hash password: {}", err),
+            AuthError::Engine(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<RusDbError> for AuthError {
+    fn from(err: RusDbError) -> Self {
+        AuthError::Engine(err)
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::Hash(e.to_string()))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug)]
+pub struct Authenticator {
+    config: AuthConfig,
+    /// Minutes a token stays valid; changes when the configuration is reloaded.
+    session_time: AtomicU32,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Self {
+        Self {
+            session_time: AtomicU32::new(config.session_time.unwrap_or(60)),
+            config,
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+    fn session_time(&self) -> Duration {
+        Duration::from_secs(self.session_time.load(Ordering::Relaxed) as u64 * 60u64)
+    }
+    /// Applies the settings of `config` that can change while running. Only
+    /// the session time does; tokens already issued keep their expiry.
+    pub fn reconfigure(&self, config: &AuthConfig) {
+        self.session_time
+            .store(config.session_time.unwrap_or(60), Ordering::Relaxed);
+    }
+    async fn find_user(&self, username: &str) -> Result<Option<User>, RusDbError> {
+        let engine = crate::ENGINE.get().await.clone();
+        let col = match engine
+            .get_collection(ADMIN_DATABASE, USERS_COLLECTION)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(None),
+        };
+        let lock = col.read().await;
+        Ok(lock
+            .values()
+            .filter_map(|doc| bson::from_document::<User>(doc.clone()).ok())
+            .find(|user| user.username == username))
+    }
+    /// Creates the configured admin account if it does not exist yet.
+    pub async fn bootstrap(&self) {
+        if !self.enabled() {
+            return;
+        }
+        if let (Some(username), Some(password)) =
+            (&self.config.admin_user, &self.config.admin_password)
+        {
+            let exists = match self.find_user(username).await {
+                Ok(user) => user.is_some(),
+                Err(e) => {
+                    error!("Unable to look up bootstrap admin: {}", e);
+                    return;
+                }
+            };
+            if !exists {
+                info!("Creating bootstrap admin user {}...", username);
+                if let Err(e) = self
+                    .create_user(username, password, vec![ROOT_ROLE.to_string()])
+                    .await
+                {
+                    error!("Unable to create bootstrap admin: {}", e);
+                }
+            }
+        } else {
+            warn!("Authentication is enabled but no bootstrap admin is configured.");
+        }
+    }
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        roles: Vec<String>,
+    ) -> Result<Uuid, AuthError> {
+        for role in &roles {
+            if self.find_role(role).await?.is_none() {
+                return Err(AuthError::RoleNotFound(role.clone()));
+            }
+        }
+        let user = User {
+            username: username.to_string(),
+            password: hash_password(password)?,
+            roles,
+        };
+        let engine = crate::ENGINE.get().await.clone();
+        let col = engine
+            .ensure_collection(ADMIN_DATABASE, USERS_COLLECTION)
+            .await?;
+        // Checked under the same lock as the insert, so two requests cannot
+        // both create the user. The password is hashed before taking it.
+        let mut lock = col.write().await;
+        let exists = lock
+            .values()
+            .any(|doc| doc.get_str("username") == Ok(username));
+        if exists {
+            return Err(AuthError::UserExists(username.to_string()));
+        }
+        let id = Uuid::new_v4();
+        let mut doc: Document = bson::to_document(&user).map_err(RusDbError::encode)?;
+        doc.insert("_id", id);
+        lock.insert(id.into(), doc);
+        Ok(id)
+    }
+    pub async fn drop_user(&self, username: &str) -> Result<(), AuthError> {
+        let engine = crate::ENGINE.get().await.clone();
+        let col = engine
+            .get_collection(ADMIN_DATABASE, USERS_COLLECTION)
+            .await?
+            .ok_or_else(|| AuthError::UserNotFound(username.to_string()))?;
+        let mut lock = col.write().await;
+        let before = lock.len();
+        lock.retain(|_, doc| doc.get_str("username") != Ok(username));
+        if lock.len() == before {
+            return Err(AuthError::UserNotFound(username.to_string()));
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, s| s.identity.username != username);
+        Ok(())
+    }
+    /// Verifies the credentials and issues a session token with its expiry.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Option<(String, SystemTime)> {
+        let user = match self.find_user(username).await {
+            Ok(user) => user?,
+            Err(e) => {
+                error!("Unable to look up user {}: {}", username, e);
+                return None;
+            }
+        };
+        if !verify_password(password, &user.password) {
+            return None;
+        }
+        let token = new_token();
+        let expires = SystemTime::now() + self.session_time();
+        let mut sessions = self.sessions.write().unwrap();
+        let now = SystemTime::now();
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                identity: Identity {
+                    username: user.username,
+                },
+                expires,
+            },
+        );
+        Some((token, expires))
+    }
+    pub fn validate(&self, token: &str) -> Option<Identity> {
+        let sessions = self.sessions.read().unwrap();
+        match sessions.get(token) {
+            Some(session) if session.expires > SystemTime::now() => Some(session.identity.clone()),
+            _ => None,
+        }
+    }
+    /// Interceptor for the `RusDb` service requiring a valid bearer token or
+    /// a verified client certificate whose common name is a rusdb user.
+    pub fn intercept(&self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.enabled() {
+            return Ok(request);
+        }
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string());
+        match token {
+            Some(token) => match self.validate(&token) {
+                Some(identity) => {
+                    request.extensions_mut().insert(identity);
+                    Ok(request)
+                }
+                None => Err(Status::unauthenticated(
+                    "Session token is invalid or expired.",
+                )),
+            },
+            None => {
+                // Fall back to the subject of a verified client certificate.
+                let subject = request
+                    .peer_certs()
+                    .and_then(|certs| certs.first().and_then(|c| subject_name(c.get_ref())));
+                match subject {
+                    Some(username) => {
+                        request.extensions_mut().insert(Identity { username });
+                        Ok(request)
+                    }
+                    None => Err(Status::unauthenticated("Missing bearer token.")),
+                }
+            }
+        }
+    }
+    async fn find_role(&self, name: &str) -> Result<Option<Role>, RusDbError> {
+        if name == ROOT_ROLE {
+            return Ok(Some(Role::root()));
+        }
+        let engine = crate::ENGINE.get().await.clone();
+        let col = match engine
+            .get_collection(ADMIN_DATABASE, ROLES_COLLECTION)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(None),
+        };
+        let lock = col.read().await;
+        Ok(lock
+            .values()
+            .filter_map(|doc| bson::from_document::<Role>(doc.clone()).ok())
+            .find(|role| role.name == name))
+    }
+    pub async fn create_role(&self, role: Role) -> Result<(), AuthError> {
+        if role.name == ROOT_ROLE {
+            return Err(AuthError::RoleExists(role.name));
+        }
+        let engine = crate::ENGINE.get().await.clone();
+        let col = engine
+            .ensure_collection(ADMIN_DATABASE, ROLES_COLLECTION)
+            .await?;
+        // Checked under the same lock as the insert, so two requests cannot
+        // both create the role.
+        let mut lock = col.write().await;
+        let exists = lock
+            .values()
+            .any(|doc| doc.get_str("name") == Ok(role.name.as_str()));
+        if exists {
+            return Err(AuthError::RoleExists(role.name));
+        }
+        let id = Uuid::new_v4();
+        let mut doc: Document = bson::to_document(&role).map_err(RusDbError::encode)?;
+        doc.insert("_id", id);
+        lock.insert(id.into(), doc);
+        Ok(())
+    }
+    pub async fn drop_role(&self, name: &str) -> Result<(), AuthError> {
+        let engine = crate::ENGINE.get().await.clone();
+        let col = engine
+            .get_collection(ADMIN_DATABASE, ROLES_COLLECTION)
+            .await?
+            .ok_or_else(|| AuthError::RoleNotFound(name.to_string()))?;
+        let mut lock = col.write().await;
+        let before = lock.len();
+        lock.retain(|_, doc| doc.get_str("name") != Ok(name));
+        if lock.len() == before {
+            return Err(AuthError::RoleNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+    /// Adds or removes `role` on the user, returning whether anything changed.
+    pub async fn set_role(
+        &self,
+        username: &str,
+        role: &str,
+        grant: bool,
+    ) -> Result<bool, AuthError> {
+        if grant && self.find_role(role).await?.is_none() {
+            return Err(AuthError::RoleNotFound(role.to_string()));
+        }
+        let engine = crate::ENGINE.get().await.clone();
+        let col = engine
+            .get_collection(ADMIN_DATABASE, USERS_COLLECTION)
+            .await?
+            .ok_or_else(|| AuthError::UserNotFound(username.to_string()))?;
+        let mut lock = col.write().await;
+        // Records that do not decode are skipped, as in `find_user`.
+        let (doc, mut user) = lock
+            .values_mut()
+            .filter(|doc| doc.get_str("username") == Ok(username))
+            .find_map(|doc| {
+                let user = bson::from_document::<User>(doc.clone()).ok()?;
+                Some((doc, user))
+            })
+            .ok_or_else(|| AuthError::UserNotFound(username.to_string()))?;
+        let had = user.roles.iter().any(|r| r == role);
+        if grant == had {
+            return Ok(false);
+        }
+        if grant {
+            user.roles.push(role.to_string());
+        } else {
+            user.roles.retain(|r| r != role);
+        }
+        doc.insert(
+            "roles",
+            bson::to_bson(&user.roles).map_err(RusDbError::encode)?,
+        );
+        Ok(true)
+    }
+    /// Rejects the request unless auth is disabled or one of the caller's roles
+    /// grants at least `privilege` over `scope`.
+    pub async fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        privilege: Privilege,
+        scope: Scope<'_>,
+    ) -> Result<(), Status> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let identity = match request.extensions().get::<Identity>() {
+            Some(identity) => identity,
+            None => return Err(Status::unauthenticated("Request is not authenticated.")),
+        };
+        let roles = match self.find_user(&identity.username).await? {
+            Some(user) => user.roles,
+            None => return Err(Status::unauthenticated("User no longer exists.")),
+        };
+        for name in &roles {
+            if let Some(role) = self.find_role(name).await? {
+                if role.privileges.iter().any(|g| g.covers(privilege, scope)) {
+                    return Ok(());
+                }
+            }
+        }
+        Err(Status::permission_denied(format!(
+            "User {} is missing the {} privilege on {}.",
+            identity.username, privilege, scope
+        )))
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const ROLES_COLLECTION: &str = "system.roles";
+
+pub const ROOT_ROLE: &str = "root";
+
+/// Privileges are ordered: admin implies write, and write implies read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Privilege {
+    Read,
+    Write,
+    Admin,
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Privilege::Read => write!(f, "read"),
+            Privilege::Write => write!(f, "write"),
+            Privilege::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// What a privilege check is about: every database, a whole database, or a
+/// single collection in one.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope<'a> {
+    All,
+    Database(&'a str),
+    Collection(&'a str, &'a str),
+}
+
+impl fmt::Display for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::All => write!(f, "all databases"),
+            Scope::Database(name) => write!(f, "database {}", name),
+            Scope::Collection(database, name) => write!(f, "collection {}.{}", database, name),
+        }
+    }
+}
+
+fn any_database() -> String {
+    "*".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    pub privilege: Privilege,
+    /// Database name pattern. Grants stored before databases existed apply
+    /// to every database.
+    #[serde(default = "any_database")]
+    pub database: String,
+    /// Collection name pattern where `*` matches any run of characters.
+    pub collection: String,
+}
+
+impl Grant {
+    pub fn covers(&self, privilege: Privilege, scope: Scope) -> bool {
+        if self.privilege < privilege {
+            return false;
+        }
+        match scope {
+            Scope::All => self.database == "*" && self.collection == "*",
+            Scope::Database(database) => {
+                pattern_matches(&self.database, database) && self.collection == "*"
+            }
+            Scope::Collection(database, name) => {
+                pattern_matches(&self.database, database) && pattern_matches(&self.collection, name)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    pub privileges: Vec<Grant>,
+}
+
+impl Role {
+    pub fn root() -> Self {
+        Self {
+            name: ROOT_ROLE.to_string(),
+            privileges: vec![Grant {
+                privilege: Privilege::Admin,
+                database: any_database(),
+                collection: "*".to_string(),
+            }],
+        }
+    }
+}
+
+pub fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    if parts.is_empty() {
+        return rest.is_empty();
+    }
+    let (last, middle) = parts.split_last().unwrap();
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
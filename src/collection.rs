@@ -0,0 +1,254 @@
+//! Typed access to a single collection for programs embedding the engine.
+
+use crate::engine::error::RusDbError;
+use crate::engine::id::DocId;
+use crate::engine::RusDbEngine;
+use crate::write::{self, Writer};
+use bson::Document;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// How `Collection::find` walks the collection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FindOptions {
+    pub limit: Option<u32>,
+    /// Return documents newest first instead of in insertion order.
+    pub reverse: bool,
+}
+
+/// A handle on one collection whose documents are stored as `T`, obtained
+/// from `RusDbEngine::collection`. Reads of a collection that does not exist
+/// see it empty, and inserts create it unless `auto_create` is `never`.
+pub struct Collection<T = Document> {
+    engine: Arc<RusDbEngine>,
+    database: String,
+    name: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Collection<T> {
+    fn clone(&self) -> Self {
+        Self {
+            engine: self.engine.clone(),
+            database: self.database.clone(),
+            name: self.name.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+fn encode<T: Serialize>(doc: &T) -> Result<Document, RusDbError> {
+    bson::to_document(doc).map_err(RusDbError::encode)
+}
+
+fn decode<T: DeserializeOwned>(doc: Document) -> Result<T, RusDbError> {
+    bson::from_document(doc)
+        .map_err(|e| RusDbError::Invalid(format!("Unable to decode a document: {}", e)))
+}
+
+impl<T> Collection<T> {
+    pub(crate) fn new(engine: Arc<RusDbEngine>, database: String, name: String) -> Self {
+        Self {
+            engine,
+            database,
+            name,
+            marker: PhantomData,
+        }
+    }
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T> Collection<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Inserts a document and returns its `_id`.
+    pub async fn insert_one(&self, doc: &T) -> Result<DocId, RusDbError> {
+        let mut ids = self.insert_documents(vec![encode(doc)?]).await?;
+        Ok(ids.remove(0))
+    }
+    /// Inserts every document, or none of them if any fails validation or
+    /// repeats an `_id`. Documents without an `_id` get one from the
+    /// collection's id strategy.
+    pub async fn insert_many(&self, docs: &[T]) -> Result<Vec<DocId>, RusDbError> {
+        let docs = docs.iter().map(encode).collect::<Result<Vec<_>, _>>()?;
+        self.insert_documents(docs).await
+    }
+    /// Like `insert_many`, taking the documents by value.
+    pub async fn insert(
+        &self,
+        docs: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<DocId>, RusDbError> {
+        let docs = docs
+            .into_iter()
+            .map(|doc| encode(&doc))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.insert_documents(docs).await
+    }
+    async fn insert_documents(&self, docs: Vec<Document>) -> Result<Vec<DocId>, RusDbError> {
+        let col = self
+            .engine
+            .auto_collection(&self.database, &self.name, true)
+            .await?
+            .ok_or_else(|| RusDbError::CollectionNotFound(self.name.clone()))?;
+        let options = self
+            .engine
+            .collection_options(&self.database, &self.name)
+            .await?;
+        let writer = Writer {
+            engine: &self.engine,
+            options: options.as_deref(),
+            database: &self.database,
+            collection: &self.name,
+        };
+        let prepared = docs
+            .into_iter()
+            .map(|doc| write::prepare(writer.options, &self.database, &self.name, doc))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut lock = col.write().await;
+        write::check_duplicates(&lock, prepared.iter().map(|(id, _)| id))?;
+        let mut ids = Vec::with_capacity(prepared.len());
+        for (id, doc) in prepared {
+            writer.insert(&mut lock, id.clone(), doc).await?;
+            ids.push(id);
+        }
+        writer.enforce_cap(&mut lock).await?;
+        Ok(ids)
+    }
+    /// Documents whose fields equal every field of `filter`.
+    pub async fn find(
+        &self,
+        filter: &Document,
+        options: FindOptions,
+    ) -> Result<Vec<T>, RusDbError> {
+        let col = match self
+            .engine
+            .auto_collection(&self.database, &self.name, false)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(vec![]),
+        };
+        let lock = col.read().await;
+        let docs: Box<dyn Iterator<Item = &Document>> = if options.reverse {
+            Box::new((*lock).values().rev())
+        } else {
+            Box::new((*lock).values())
+        };
+        let matching = docs
+            .filter(|doc| write::matches(filter, doc))
+            .take(options.limit.map_or(usize::MAX, |limit| limit as usize));
+        matching.map(|doc| decode(doc.clone())).collect()
+    }
+    /// The first document whose fields equal every field of `filter`.
+    pub async fn find_one(&self, filter: &Document) -> Result<Option<T>, RusDbError> {
+        let options = FindOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        Ok(self.find(filter, options).await?.pop())
+    }
+    pub async fn get(&self, id: &DocId) -> Result<Option<T>, RusDbError> {
+        let col = match self
+            .engine
+            .auto_collection(&self.database, &self.name, false)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(None),
+        };
+        let lock = col.read().await;
+        (*lock).get(id).cloned().map(decode).transpose()
+    }
+    /// Sets the fields of `updates` on up to `limit` matching documents and
+    /// returns them. Nothing changes unless every one passes validation.
+    pub async fn update(
+        &self,
+        filter: &Document,
+        updates: &Document,
+        limit: Option<u32>,
+    ) -> Result<Vec<T>, RusDbError> {
+        if updates.is_empty() {
+            return Err(RusDbError::Invalid("Updates document is empty.".into()));
+        }
+        let col = match self
+            .engine
+            .auto_collection(&self.database, &self.name, false)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(vec![]),
+        };
+        let options = self
+            .engine
+            .collection_options(&self.database, &self.name)
+            .await?;
+        let writer = Writer {
+            engine: &self.engine,
+            options: options.as_deref(),
+            database: &self.database,
+            collection: &self.name,
+        };
+        let mut lock = col.write().await;
+        let updated = writer.update(&mut lock, filter, updates, limit).await?;
+        writer.enforce_cap(&mut lock).await?;
+        updated.into_iter().map(decode).collect()
+    }
+    /// Swaps the first matching document for `replacement` and returns the
+    /// stored document, or `None` if nothing matched.
+    pub async fn replace(
+        &self,
+        filter: &Document,
+        replacement: &T,
+    ) -> Result<Option<T>, RusDbError> {
+        let replacement = encode(replacement)?;
+        let col = match self
+            .engine
+            .auto_collection(&self.database, &self.name, false)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(None),
+        };
+        let options = self
+            .engine
+            .collection_options(&self.database, &self.name)
+            .await?;
+        let writer = Writer {
+            engine: &self.engine,
+            options: options.as_deref(),
+            database: &self.database,
+            collection: &self.name,
+        };
+        let mut lock = col.write().await;
+        let replaced = writer.replace(&mut lock, filter, replacement).await?;
+        writer.enforce_cap(&mut lock).await?;
+        replaced.map(decode).transpose()
+    }
+    /// Removes up to `limit` matching documents and returns how many went.
+    pub async fn remove(&self, filter: &Document, limit: Option<u32>) -> Result<u32, RusDbError> {
+        let col = match self
+            .engine
+            .auto_collection(&self.database, &self.name, false)
+            .await?
+        {
+            Some(col) => col,
+            None => return Ok(0),
+        };
+        let writer = Writer {
+            engine: &self.engine,
+            options: None,
+            database: &self.database,
+            collection: &self.name,
+        };
+        let mut lock = col.write().await;
+        writer.remove(&mut lock, filter, limit).await
+    }
+}
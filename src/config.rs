@@ -1,30 +1,98 @@
+use crate::engine::error::RusDbError;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use tokio::fs;
-use toml;
+use toml::value::{Table, Value};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GrpcConfig {
-    pub ip: String,
-    pub port: u32,
+    /// TCP address; leave both unset to serve only on `unix_socket`.
+    pub ip: Option<String>,
+    pub port: Option<u32>,
+    pub unix_socket: Option<String>,
+    /// Permission bits of the socket file, e.g. `0o660`.
+    pub unix_socket_mode: Option<u32>,
+    pub tls: Option<TlsConfig>,
 }
 
 impl Default for GrpcConfig {
     fn default() -> Self {
         Self {
-            ip: "127.0.0.1".to_string(),
-            port: 8009,
+            ip: Some("127.0.0.1".to_string()),
+            port: Some(8009),
+            unix_socket: None,
+            unix_socket_mode: None,
+            tls: None,
         }
     }
 }
 
+/// Optional REST listener speaking extended JSON.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpConfig {
+    pub ip: String,
+    pub port: u32,
+}
+
+/// Optional listener speaking the MongoDB wire protocol.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MongoConfig {
+    pub ip: String,
+    pub port: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub cert: String,
+    pub key: String,
+    pub client_ca: Option<String>,
+    pub require_client_cert: Option<bool>,
+}
+
+/// How the server process runs, apart from any one listener.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ServerConfig {
+    /// Seconds in-flight requests get to finish once shutdown begins.
+    pub shutdown_timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct EngineConfig {
     pub cache_time: u32,
     pub flush_time: u32,
     pub dir: Option<String>,
+    pub oplog_retention: Option<u64>,
+    pub key_file: Option<String>,
+    pub auto_create: Option<AutoCreate>,
+    pub ttl_interval: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// When a collection that does not exist yet is created implicitly.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutoCreate {
+    /// Any request naming the collection creates it, reads included.
+    Always,
+    /// Only inserts create it; reads see an empty collection.
+    #[default]
+    OnWriteOnly,
+    /// Collections must be created with `CreateCollection`.
+    Never,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub admin_user: Option<String>,
+    pub admin_password: Option<String>,
+    pub session_time: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct LogConfig {
     pub path: Option<String>,
     pub level: Option<u8>,
@@ -52,57 +120,601 @@ impl LogConfig {
     }
 }
 
-impl Default for LogConfig {
-    fn default() -> Self {
-        Self {
-            path: None,
-            level: None,
-        }
-    }
-}
-
 impl Default for EngineConfig {
     fn default() -> Self {
         Self {
             cache_time: 1,
             flush_time: 10,
             dir: None,
+            oplog_retention: None,
+            key_file: None,
+            auto_create: None,
+            ttl_interval: None,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
 pub struct RusDbConfig {
+    pub server: ServerConfig,
     pub grpc: GrpcConfig,
+    pub http: Option<HttpConfig>,
+    pub mongo: Option<MongoConfig>,
     pub engine: EngineConfig,
     pub logging: Option<LogConfig>,
+    pub auth: Option<AuthConfig>,
+    /// The file the configuration was read from, if any.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
 }
 
-pub async fn load() -> RusDbConfig {
-    let exists = match fs::metadata(".rusdb.toml").await {
-        Ok(meta) => {
-            if meta.is_file() {
-                true
-            } else {
-                panic!("config file is not a file");
+/// One invalid setting found by `RusDbConfig::validate`.
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    /// The dotted key, e.g. `engine.cache_time`.
+    pub key: String,
+    pub message: String,
+    /// How to fix it.
+    pub hint: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}. Hint: {}", self.key, self.message, self.hint)?;
+        if std::env::var_os(env_name(&self.key)).is_some() {
+            write!(f, " (set by {})", env_name(&self.key))?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the problems of one configuration.
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn add(&mut self, key: &str, message: impl Into<String>, hint: impl Into<String>) {
+        self.0.push(ConfigProblem {
+            key: key.to_string(),
+            message: message.into(),
+            hint: hint.into(),
+        });
+    }
+    /// Listening addresses must parse the way the servers parse them.
+    fn address(&mut self, section: &str, ip: &str, port: u32) -> Option<SocketAddr> {
+        let mut valid = true;
+        if port == 0 || port > u16::MAX as u32 {
+            self.add(
+                &format!("{}.port", section),
+                format!("{} is not a valid port", port),
+                "use a port between 1 and 65535",
+            );
+            valid = false;
+        }
+        if format!("{}:0", ip).parse::<SocketAddr>().is_err() {
+            self.add(
+                &format!("{}.ip", section),
+                format!("{:?} is not an IP address", ip),
+                "use an address such as 127.0.0.1 or 0.0.0.0, and brackets for IPv6, e.g. [::1]",
+            );
+            valid = false;
+        }
+        if valid {
+            format!("{}:{}", ip, port).parse().ok()
+        } else {
+            None
+        }
+    }
+    fn file(&mut self, key: &str, path: &str) {
+        if !Path::new(path).is_file() {
+            self.add(
+                key,
+                format!("{} does not exist or is not a file", path),
+                "relative paths are resolved from the working directory of the server",
+            );
+        }
+    }
+    fn at_least_one(&mut self, key: &str, value: u64, hint: &str) {
+        if value == 0 {
+            self.add(key, "must be at least 1, not 0", hint);
+        }
+    }
+}
+
+impl RusDbConfig {
+    /// Checks the values deserialization cannot, such as addresses, ranges
+    /// and referenced files, and returns every problem found.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Problems::default();
+        let grpc = &self.grpc;
+        let mut listeners: Vec<(&str, SocketAddr)> = vec![];
+        match (&grpc.ip, grpc.port) {
+            (Some(ip), Some(port)) => {
+                if let Some(addr) = problems.address("grpc", ip, port) {
+                    listeners.push(("grpc", addr));
+                }
             }
+            (None, None) if grpc.unix_socket.is_none() => problems.add(
+                "grpc",
+                "no listener is configured",
+                "set ip and port, unix_socket, or both",
+            ),
+            (None, None) => {}
+            (Some(_), None) => problems.add(
+                "grpc.port",
+                "is missing while grpc.ip is set",
+                "set both ip and port, or neither to serve only on unix_socket",
+            ),
+            (None, Some(_)) => problems.add(
+                "grpc.ip",
+                "is missing while grpc.port is set",
+                "set both ip and port, or neither to serve only on unix_socket",
+            ),
         }
-        Err(_) => false,
-    };
-    if exists {
-        match fs::read(".rusdb.toml").await {
-            Ok(data) => toml::from_slice(&data).unwrap(),
-            Err(err) => {
-                panic!("unable to read config: {}", err);
+        if grpc.unix_socket.as_deref() == Some("") {
+            problems.add(
+                "grpc.unix_socket",
+                "is empty",
+                "give the path of the socket file, or leave the key out",
+            );
+        }
+        if let Some(mode) = grpc.unix_socket_mode {
+            if mode > 0o777 {
+                problems.add(
+                    "grpc.unix_socket_mode",
+                    format!("{:#o} is not a permission mode", mode),
+                    "use octal permission bits such as 0o660",
+                );
+            }
+        }
+        if let Some(tls) = &grpc.tls {
+            problems.file("grpc.tls.cert", &tls.cert);
+            problems.file("grpc.tls.key", &tls.key);
+            if let Some(ca) = &tls.client_ca {
+                problems.file("grpc.tls.client_ca", ca);
+            }
+        }
+        if let Some(http) = &self.http {
+            if let Some(addr) = problems.address("http", &http.ip, http.port) {
+                listeners.push(("http", addr));
+            }
+        }
+        if let Some(mongo) = &self.mongo {
+            if let Some(addr) = problems.address("mongo", &mongo.ip, mongo.port) {
+                listeners.push(("mongo", addr));
+            }
+            if self.auth.as_ref().is_some_and(|auth| auth.enabled) {
+                problems.add(
+                    "mongo",
+                    "cannot be used while auth.enabled is set",
+                    "the MongoDB listener has no authentication; remove [mongo] or disable [auth]",
+                );
+            }
+        }
+        for (i, (section, addr)) in listeners.iter().enumerate() {
+            if let Some((other, _)) = listeners[..i]
+                .iter()
+                .find(|(_, other)| other.port() == addr.port())
+            {
+                problems.add(
+                    &format!("{}.port", section),
+                    format!("{} is already used by {}.port", addr.port(), other),
+                    "give every listener its own port",
+                );
+            }
+        }
+        let engine = &self.engine;
+        problems.at_least_one(
+            "engine.cache_time",
+            engine.cache_time as u64,
+            "it is in minutes, and 0 would sync the cache in a busy loop",
+        );
+        problems.at_least_one(
+            "engine.flush_time",
+            engine.flush_time as u64,
+            "it is in minutes, and 0 would flush the cache in a busy loop",
+        );
+        if let Some(retention) = engine.oplog_retention {
+            problems.at_least_one(
+                "engine.oplog_retention",
+                retention,
+                "it is the number of operations kept for watchers to resume from",
+            );
+        }
+        if let Some(interval) = engine.ttl_interval {
+            problems.at_least_one(
+                "engine.ttl_interval",
+                interval,
+                "it is in seconds, and 0 would sweep TTL collections in a busy loop",
+            );
+        }
+        if let Some(dir) = &engine.dir {
+            if Path::new(dir).exists() && !Path::new(dir).is_dir() {
+                problems.add(
+                    "engine.dir",
+                    format!("{} is not a directory", dir),
+                    "point it at a directory, which is created if it does not exist",
+                );
+            }
+        }
+        if let Some(key_file) = &engine.key_file {
+            problems.file("engine.key_file", key_file);
+        }
+        if let Some(auth) = &self.auth {
+            if auth.admin_user.is_some() != auth.admin_password.is_some() {
+                let missing = match auth.admin_user {
+                    Some(_) => "auth.admin_password",
+                    None => "auth.admin_user",
+                };
+                problems.add(
+                    missing,
+                    "is missing",
+                    "set admin_user and admin_password together to create the bootstrap admin",
+                );
+            }
+            if let Some(time) = auth.session_time {
+                problems.at_least_one(
+                    "auth.session_time",
+                    time as u64,
+                    "it is the session token lifetime in minutes",
+                );
+            }
+        }
+        problems.0
+    }
+}
+
+/// Keys of `table` that are not configuration keys, such as misspellings.
+fn unknown_keys(table: &Table, prefix: &str, found: &mut Vec<ConfigProblem>) {
+    for (name, value) in table {
+        let key = match prefix {
+            "" => name.clone(),
+            _ => format!("{}.{}", prefix, name),
+        };
+        let section = format!("{}.", key);
+        if KEYS.iter().any(|(known, _)| *known == key) {
+            continue;
+        }
+        match value {
+            Value::Table(inner) if KEYS.iter().any(|(known, _)| known.starts_with(&section)) => {
+                unknown_keys(inner, &key, found)
             }
+            _ => found.push(ConfigProblem {
+                key,
+                message: "is not a configuration key".to_string(),
+                hint: "check the spelling against .rusdb.dist.toml".to_string(),
+            }),
         }
+    }
+}
+
+/// Turns problems into the error `load` returns.
+fn problems_error(problems: Vec<ConfigProblem>) -> RusDbError {
+    let lines: Vec<String> = problems.iter().map(|p| format!("  {}", p)).collect();
+    RusDbError::Config(format!(
+        "{} problem{} found\n{}",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" },
+        lines.join("\n")
+    ))
+}
+
+/// Where the configuration is read from unless another path is given.
+pub const DEFAULT_PATH: &str = ".rusdb.toml";
+
+/// Prefix of the environment variables overriding configuration values.
+const ENV_PREFIX: &str = "RUSDB_";
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+}
+
+/// Every configuration key with the type of its value. `RUSDB_` followed by
+/// the key in upper case with `_` for `.` overrides it, so
+/// `RUSDB_ENGINE_CACHE_TIME` sets `engine.cache_time`.
+const KEYS: &[(&str, Kind)] = &[
+    ("server.shutdown_timeout", Kind::Integer),
+    ("grpc.ip", Kind::String),
+    ("grpc.port", Kind::Integer),
+    ("grpc.unix_socket", Kind::String),
+    ("grpc.unix_socket_mode", Kind::Integer),
+    ("grpc.tls.cert", Kind::String),
+    ("grpc.tls.key", Kind::String),
+    ("grpc.tls.client_ca", Kind::String),
+    ("grpc.tls.require_client_cert", Kind::Boolean),
+    ("http.ip", Kind::String),
+    ("http.port", Kind::Integer),
+    ("mongo.ip", Kind::String),
+    ("mongo.port", Kind::Integer),
+    ("engine.cache_time", Kind::Integer),
+    ("engine.flush_time", Kind::Integer),
+    ("engine.dir", Kind::String),
+    ("engine.oplog_retention", Kind::Integer),
+    ("engine.key_file", Kind::String),
+    ("engine.auto_create", Kind::String),
+    ("engine.ttl_interval", Kind::Integer),
+    ("logging.path", Kind::String),
+    ("logging.level", Kind::Integer),
+    ("auth.enabled", Kind::Boolean),
+    ("auth.admin_user", Kind::String),
+    ("auth.admin_password", Kind::String),
+    ("auth.session_time", Kind::Integer),
+];
+
+/// Keys a running server applies when the configuration is reloaded. Every
+/// other key takes effect on the next start.
+pub const RELOADABLE: &[&str] = &[
+    "engine.cache_time",
+    "engine.flush_time",
+    "engine.oplog_retention",
+    "engine.auto_create",
+    "engine.ttl_interval",
+    "logging.level",
+    "auth.session_time",
+];
+
+/// The value at a dotted `key` in `table`, if it is set.
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut sections: Vec<&str> = key.split('.').collect();
+    let field = sections.pop()?;
+    let mut current = table;
+    for section in sections {
+        current = current.get(section)?.as_table()?;
+    }
+    current.get(field)
+}
+
+impl RusDbConfig {
+    /// The keys whose values differ between `self` and `other`.
+    pub fn changed_keys(&self, other: &RusDbConfig) -> Vec<&'static str> {
+        let table = |config: &RusDbConfig| match Value::try_from(config) {
+            Ok(Value::Table(table)) => table,
+            _ => Table::new(),
+        };
+        let (before, after) = (table(self), table(other));
+        KEYS.iter()
+            .map(|(key, _)| *key)
+            .filter(|key| lookup(&before, key) != lookup(&after, key))
+            .collect()
+    }
+}
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+/// Integers as written in TOML: decimal, or with a `0x`, `0o` or `0b` prefix.
+fn parse_integer(raw: &str) -> Option<i64> {
+    let raw = raw.trim().replace('_', "");
+    if let Some(hex) = raw.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(octal) = raw.strip_prefix("0o") {
+        i64::from_str_radix(octal, 8).ok()
+    } else if let Some(binary) = raw.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
     } else {
-        warn!("Unable to find config file. Creating defaults...");
-        let config = RusDbConfig::default();
-        fs::write(".rusdb.toml", toml::to_vec(&config).unwrap())
-            .await
-            .unwrap();
-        println!("Default configuration file created. Please run again.");
-        std::process::exit(1);
+        raw.parse().ok()
+    }
+}
+
+fn parse_env(name: &str, raw: &str, kind: Kind) -> Result<Value, RusDbError> {
+    match kind {
+        Kind::String => Ok(Value::String(raw.to_string())),
+        Kind::Integer => parse_integer(raw).map(Value::Integer).ok_or_else(|| {
+            RusDbError::Config(format!("{} must be an integer, not {:?}", name, raw))
+        }),
+        Kind::Boolean => match raw.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(Value::Boolean(true)),
+            "false" | "0" | "no" | "off" => Ok(Value::Boolean(false)),
+            _ => Err(RusDbError::Config(format!(
+                "{} must be true or false, not {:?}",
+                name, raw
+            ))),
+        },
+    }
+}
+
+/// Sets the values of `RUSDB_*` variables in `table` as if they had been
+/// written in the configuration file. An empty value removes the key, and
+/// variables matching no key are added to `found`.
+fn apply_env(
+    table: &mut Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+    found: &mut Vec<ConfigProblem>,
+) -> Result<(), RusDbError> {
+    for (name, raw) in vars {
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+        let (key, kind) = match KEYS.iter().find(|(key, _)| env_name(key) == name) {
+            Some(key) => key,
+            None => {
+                found.push(ConfigProblem {
+                    key: name,
+                    message: "is not a configuration variable".to_string(),
+                    hint: "name it after a key, e.g. RUSDB_ENGINE_CACHE_TIME for engine.cache_time"
+                        .to_string(),
+                });
+                continue;
+            }
+        };
+        let value = if raw.is_empty() {
+            None
+        } else {
+            Some(parse_env(&name, &raw, *kind)?)
+        };
+        let mut sections: Vec<&str> = key.split('.').collect();
+        let field = sections.pop().unwrap();
+        let mut current = &mut *table;
+        for section in sections {
+            current = match current
+                .entry(section)
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(inner) => inner,
+                _ => {
+                    return Err(RusDbError::Config(format!(
+                        "{} cannot override {}, which is not a table",
+                        name, section
+                    )))
+                }
+            };
+        }
+        match value {
+            Some(value) => current.insert(field.to_string(), value),
+            None => current.remove(field),
+        };
+    }
+    Ok(())
+}
+
+/// Reads the configuration file at `path`, or `.rusdb.toml` when none is
+/// given, applies `RUSDB_*` environment overrides and then `data_dir`, and
+/// validates the result. Missing sections and a missing default file fall
+/// back to the defaults; a path given explicitly has to exist.
+pub async fn load(path: Option<&Path>, data_dir: Option<&str>) -> Result<RusDbConfig, RusDbError> {
+    let explicit = path.is_some();
+    let path = path.unwrap_or_else(|| Path::new(DEFAULT_PATH));
+    let file: Option<Table> = match fs::metadata(path).await {
+        Ok(meta) if !meta.is_file() => {
+            return Err(RusDbError::Config(format!(
+                "{} is not a file",
+                path.display()
+            )))
+        }
+        Ok(_) => {
+            let data = fs::read(path).await.map_err(|e| RusDbError::io(path, e))?;
+            Some(toml::from_slice(&data).map_err(|e| {
+                RusDbError::Config(format!("unable to parse {}: {}", path.display(), e))
+            })?)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => None,
+        Err(e) => return Err(RusDbError::io(path, e)),
+    };
+    let source = file.as_ref().map(|_| path.to_path_buf());
+    let mut table = file.unwrap_or_default();
+    let mut problems = vec![];
+    unknown_keys(&table, "", &mut problems);
+    // Sections missing from the file start out with their defaults, so that
+    // overriding one value does not drop the others.
+    if let Value::Table(defaults) =
+        Value::try_from(RusDbConfig::default()).map_err(RusDbError::encode)?
+    {
+        for (section, value) in defaults {
+            table.entry(section).or_insert(value);
+        }
+    }
+    apply_env(
+        &mut table,
+        std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }),
+        &mut problems,
+    )?;
+    let mut config: RusDbConfig = Value::Table(table)
+        .try_into()
+        .map_err(|e| RusDbError::Config(e.to_string()))?;
+    config.source = source;
+    if let Some(dir) = data_dir {
+        config.engine.dir = Some(dir.to_string());
+    }
+    problems.extend(config.validate());
+    if !problems.is_empty() {
+        return Err(problems_error(problems));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(config: &RusDbConfig) -> Vec<String> {
+        config.validate().into_iter().map(|p| p.key).collect()
+    }
+
+    #[test]
+    fn the_default_configuration_is_valid() {
+        assert!(keys(&RusDbConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn grpc_needs_a_listener_and_both_ip_and_port() {
+        let mut config = RusDbConfig::default();
+        config.grpc.port = None;
+        assert_eq!(keys(&config), vec!["grpc.port"]);
+        config.grpc.ip = None;
+        assert_eq!(keys(&config), vec!["grpc"]);
+        config.grpc.unix_socket = Some("rusdb.sock".to_string());
+        assert!(keys(&config).is_empty());
+    }
+
+    #[test]
+    fn addresses_and_ports_are_checked() {
+        let mut config = RusDbConfig::default();
+        config.grpc.ip = Some("localhost".to_string());
+        config.http = Some(HttpConfig {
+            ip: "127.0.0.1".to_string(),
+            port: 70000,
+        });
+        assert_eq!(keys(&config), vec!["grpc.ip", "http.port"]);
+    }
+
+    #[test]
+    fn listeners_need_their_own_port() {
+        let config = RusDbConfig {
+            mongo: Some(MongoConfig {
+                ip: "0.0.0.0".to_string(),
+                port: 8009,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(keys(&config), vec!["mongo.port"]);
+    }
+
+    #[test]
+    fn mongo_is_rejected_with_auth_enabled() {
+        let mut config = RusDbConfig {
+            mongo: Some(MongoConfig {
+                ip: "127.0.0.1".to_string(),
+                port: 27017,
+            }),
+            auth: Some(AuthConfig {
+                enabled: true,
+                admin_user: Some("admin".to_string()),
+                admin_password: Some("secret".to_string()),
+                session_time: None,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(keys(&config), vec!["mongo"]);
+        config.auth.as_mut().unwrap().enabled = false;
+        assert!(keys(&config).is_empty());
+    }
+
+    #[test]
+    fn zero_intervals_and_missing_files_are_problems() {
+        let mut config = RusDbConfig::default();
+        config.engine.cache_time = 0;
+        config.engine.ttl_interval = Some(0);
+        config.grpc.tls = Some(TlsConfig {
+            cert: "/nonexistent/cert.pem".to_string(),
+            key: "/nonexistent/key.pem".to_string(),
+            client_ca: None,
+            require_client_cert: None,
+        });
+        assert_eq!(
+            keys(&config),
+            vec![
+                "grpc.tls.cert",
+                "grpc.tls.key",
+                "engine.cache_time",
+                "engine.ttl_interval"
+            ]
+        );
     }
 }
@@ -0,0 +1,219 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+use std::path::{Component, Path};
+
+/// Prefix marking a sealed file so plaintext data can still be recognised.
+pub const MAGIC: &[u8; 4] = b"RDBE";
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug)]
+pub enum CryptoError {
+    KeyFile(String),
+    Encrypted,
+    Plaintext,
+    Decrypt,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::KeyFile(err) => write!(f, "unable to load key file: {}", err),
+            CryptoError::Encrypted => write!(f, "data is encrypted but no key is configured"),
+            CryptoError::Plaintext => write!(
+                f,
+                "data is not encrypted but a key is configured; encrypt it with rotate-key"
+            ),
+            CryptoError::Decrypt => {
+                write!(f, "unable to decrypt data; wrong key or corrupted file")
+            }
+        }
+    }
+}
+
+/// ChaCha20-Poly1305 sealing of persisted files. Every sealed blob carries its
+/// own random nonce: `MAGIC || nonce || ciphertext`. The associated data is
+/// the file's path within the data directory (see `file_aad`), so a sealed
+/// file does not open in place of another.
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Loads a 256-bit key stored either as 32 raw bytes or 64 hex characters.
+    pub fn from_key_file(path: &Path) -> Result<Self, CryptoError> {
+        let data = std::fs::read(path)
+            .map_err(|e| CryptoError::KeyFile(format!("{}: {}", path.display(), e)))?;
+        let key: Vec<u8> = if data.len() == 32 {
+            data
+        } else {
+            let text = String::from_utf8_lossy(&data);
+            let text = text.trim();
+            if text.len() != 64 || !text.is_ascii() {
+                return Err(CryptoError::KeyFile(format!(
+                    "{}: expected 32 raw bytes or 64 hex characters",
+                    path.display()
+                )));
+            }
+            (0..64)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| CryptoError::KeyFile(format!("{}: invalid hex key", path.display())))?
+        };
+        Ok(Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = self.aead.encrypt(&nonce, payload).unwrap();
+        let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend(ciphertext);
+        out
+    }
+    pub fn open(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if !is_sealed(data) || data.len() < MAGIC.len() + NONCE_LEN {
+            return Err(CryptoError::Decrypt);
+        }
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        self.aead
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| CryptoError::Decrypt)
+    }
+}
+
+/// The associated data of a file: its path relative to the data directory
+/// `root`, with `/` separators, e.g. `databases/default/users.bson`.
+pub fn file_aad(root: &Path, path: &Path) -> Vec<u8> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+        .into_bytes()
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Seals `data` when a cipher is configured, otherwise passes it through.
+pub fn encode(cipher: Option<&Cipher>, aad: &[u8], data: Vec<u8>) -> Vec<u8> {
+    match cipher {
+        Some(cipher) => cipher.seal(&data, aad),
+        None => data,
+    }
+}
+
+/// Opens sealed data. With a cipher configured, plaintext is rejected: it
+/// could have been put in place by anyone able to write the data directory.
+pub fn decode(cipher: Option<&Cipher>, aad: &[u8], data: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+    match (cipher, is_sealed(&data)) {
+        (Some(cipher), true) => cipher.open(&data, aad),
+        (Some(_), false) => Err(CryptoError::Plaintext),
+        (None, true) => Err(CryptoError::Encrypted),
+        (None, false) => Ok(data),
+    }
+}
+
+/// Like `decode`, for `rotate-key` and moving files out of `collections/`
+/// only: also accepts plaintext and data sealed before files were bound to
+/// their path.
+pub fn decode_migrating(
+    cipher: Option<&Cipher>,
+    aad: &[u8],
+    data: Vec<u8>,
+) -> Result<Vec<u8>, CryptoError> {
+    match (cipher, is_sealed(&data)) {
+        (Some(cipher), true) => cipher.open(&data, aad).or_else(|_| cipher.open(&data, &[])),
+        (_, false) => Ok(data),
+        (None, true) => Err(CryptoError::Encrypted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> Cipher {
+        let path =
+            std::env::temp_dir().join(format!("rusdb-test-{}-{}.key", std::process::id(), byte));
+        std::fs::write(&path, [byte; 32]).unwrap();
+        let cipher = Cipher::from_key_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        cipher
+    }
+
+    #[test]
+    fn sealed_data_opens_with_the_same_aad() {
+        let cipher = cipher(1);
+        let sealed = cipher.seal(b"data", b"databases/default/a.bson");
+        assert!(is_sealed(&sealed));
+        assert_eq!(
+            cipher.open(&sealed, b"databases/default/a.bson").unwrap(),
+            b"data"
+        );
+    }
+
+    #[test]
+    fn sealed_data_does_not_open_with_another_aad_or_key() {
+        let sealed = cipher(1).seal(b"data", b"databases/default/a.bson");
+        assert!(matches!(
+            cipher(1).open(&sealed, b"databases/default/b.bson"),
+            Err(CryptoError::Decrypt)
+        ));
+        assert!(matches!(
+            cipher(2).open(&sealed, b"databases/default/a.bson"),
+            Err(CryptoError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_the_wrong_side() {
+        let cipher = cipher(1);
+        assert!(matches!(
+            decode(Some(&cipher), b"a", b"plain".to_vec()),
+            Err(CryptoError::Plaintext)
+        ));
+        assert!(matches!(
+            decode(None, b"a", cipher.seal(b"data", b"a")),
+            Err(CryptoError::Encrypted)
+        ));
+    }
+
+    #[test]
+    fn decode_migrating_accepts_data_sealed_without_aad() {
+        let cipher = cipher(1);
+        let sealed = cipher.seal(b"data", &[]);
+        assert!(decode(Some(&cipher), b"a", sealed.clone()).is_err());
+        assert_eq!(
+            decode_migrating(Some(&cipher), b"a", sealed).unwrap(),
+            b"data"
+        );
+    }
+
+    #[test]
+    fn file_aad_is_relative_to_the_root() {
+        let root = Path::new("/data/rusdb");
+        assert_eq!(
+            file_aad(root, &root.join("databases").join("default").join("a.bson")),
+            b"databases/default/a.bson"
+        );
+    }
+}
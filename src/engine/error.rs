@@ -0,0 +1,149 @@
+use super::crypto::CryptoError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong inside the engine or while loading its
+/// configuration. None of these take the server down; handlers turn them
+/// into a `tonic::Status` (with the `grpc` feature) and background tasks log
+/// them.
+#[derive(Debug)]
+pub enum RusDbError {
+    /// Reading or writing a file failed.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A file was read but its contents could not be decoded.
+    Corrupt {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A value could not be encoded to BSON or TOML.
+    Encode(String),
+    Crypto(CryptoError),
+    Config(String),
+    CollectionNotFound(String),
+    CollectionExists(String),
+    /// A document with the same `_id` is already in the collection or
+    /// earlier in the same batch.
+    DuplicateId(String),
+    /// A request or document was rejected, e.g. by a validator.
+    Invalid(String),
+}
+
+impl RusDbError {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        RusDbError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+    pub fn corrupt(path: &Path, reason: impl fmt::Display) -> Self {
+        RusDbError::Corrupt {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        }
+    }
+    pub fn encode(reason: impl fmt::Display) -> Self {
+        RusDbError::Encode(reason.to_string())
+    }
+    /// Short machine readable name of the variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RusDbError::Io { .. } => "io",
+            RusDbError::Corrupt { .. } => "corrupt",
+            RusDbError::Encode(_) => "encode",
+            RusDbError::Crypto(_) => "crypto",
+            RusDbError::Config(_) => "config",
+            RusDbError::CollectionNotFound(_) => "collection_not_found",
+            RusDbError::CollectionExists(_) => "collection_exists",
+            RusDbError::DuplicateId(_) => "duplicate_id",
+            RusDbError::Invalid(_) => "invalid",
+        }
+    }
+    /// The file involved, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            RusDbError::Io { path, .. } | RusDbError::Corrupt { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+    /// The message without the file involved, for errors sent to clients.
+    pub fn public_message(&self) -> String {
+        match self {
+            RusDbError::Io { source, .. } => format!("storage error: {}", source),
+            RusDbError::Corrupt { reason, .. } => format!("stored data is corrupt: {}", reason),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RusDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RusDbError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            RusDbError::Corrupt { path, reason } => {
+                write!(f, "{} is corrupt: {}", path.display(), reason)
+            }
+            RusDbError::Encode(reason) => write!(f, "unable to encode data: {}", reason),
+            RusDbError::Crypto(err) => write!(f, "{}", err),
+            RusDbError::Config(reason) => write!(f, "invalid configuration: {}", reason),
+            RusDbError::CollectionNotFound(name) => {
+                write!(f, "Collection {} does not exist.", name)
+            }
+            RusDbError::CollectionExists(name) => write!(f, "Collection {} already exists.", name),
+            RusDbError::DuplicateId(id) => write!(f, "A document with _id {} already exists.", id),
+            RusDbError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RusDbError {}
+
+impl From<CryptoError> for RusDbError {
+    fn from(err: CryptoError) -> Self {
+        RusDbError::Crypto(err)
+    }
+}
+
+#[cfg(feature = "grpc")]
+mod status {
+    use super::{CryptoError, RusDbError};
+    use bson::doc;
+    use std::io;
+    use tonic::{Code, Status};
+
+    impl From<RusDbError> for Status {
+        /// Maps to the closest gRPC code and attaches `{kind, message}` as BSON
+        /// status details. File paths stay in the server log.
+        fn from(err: RusDbError) -> Self {
+            let code = match &err {
+                RusDbError::Io { source, .. } => match source.kind() {
+                    io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+                        Code::ResourceExhausted
+                    }
+                    _ => Code::Internal,
+                },
+                RusDbError::Corrupt { .. } | RusDbError::Crypto(CryptoError::Decrypt) => {
+                    Code::DataLoss
+                }
+                RusDbError::Crypto(_) | RusDbError::Config(_) => Code::FailedPrecondition,
+                RusDbError::Encode(_) => Code::Internal,
+                RusDbError::CollectionNotFound(_) => Code::NotFound,
+                RusDbError::CollectionExists(_) | RusDbError::DuplicateId(_) => Code::AlreadyExists,
+                RusDbError::Invalid(_) => Code::InvalidArgument,
+            };
+            if code == Code::Internal || code == Code::DataLoss || err.path().is_some() {
+                error!("{}", err);
+            }
+            let message = err.public_message();
+            let details = doc! {
+                "kind": err.kind(),
+                "message": &message,
+            };
+            let details = bson::to_vec(&details).unwrap_or_default();
+            Status::with_details(code, message, details.into())
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use bson::oid::ObjectId;
+use bson::spec::BinarySubtype;
+use bson::{Binary, Bson, DateTime};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A document `_id`. Only scalars with exact equality are accepted, so
+/// doubles, decimals, documents and arrays cannot be used.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DocId {
+    Uuid(Uuid),
+    ObjectId(ObjectId),
+    String(String),
+    /// Int32 and Int64 ids compare equal, as in MongoDB.
+    Int(i64),
+    DateTime(DateTime),
+}
+
+#[derive(Debug)]
+pub struct InvalidId(pub String);
+
+impl fmt::Display for InvalidId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cannot be used as an _id", self.0)
+    }
+}
+
+impl TryFrom<&Bson> for DocId {
+    type Error = InvalidId;
+
+    fn try_from(value: &Bson) -> Result<Self, Self::Error> {
+        match value {
+            Bson::Binary(Binary {
+                subtype: BinarySubtype::Uuid | BinarySubtype::UuidOld,
+                bytes,
+            }) if bytes.len() == 16 => Ok(DocId::Uuid(Uuid::from_slice(bytes).unwrap())),
+            Bson::ObjectId(oid) => Ok(DocId::ObjectId(*oid)),
+            Bson::String(s) => Ok(DocId::String(s.clone())),
+            Bson::Int32(n) => Ok(DocId::Int(*n as i64)),
+            Bson::Int64(n) => Ok(DocId::Int(*n)),
+            Bson::DateTime(date) => Ok(DocId::DateTime(*date)),
+            other => Err(InvalidId(format!("{:?}", other.element_type()))),
+        }
+    }
+}
+
+impl From<DocId> for Bson {
+    fn from(id: DocId) -> Self {
+        match id {
+            DocId::Uuid(uuid) => uuid.into(),
+            DocId::ObjectId(oid) => Bson::ObjectId(oid),
+            DocId::String(s) => Bson::String(s),
+            DocId::Int(n) => Bson::Int64(n),
+            DocId::DateTime(date) => Bson::DateTime(date),
+        }
+    }
+}
+
+impl From<Uuid> for DocId {
+    fn from(uuid: Uuid) -> Self {
+        DocId::Uuid(uuid)
+    }
+}
+
+impl fmt::Display for DocId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocId::Uuid(uuid) => write!(f, "{}", uuid),
+            DocId::ObjectId(oid) => write!(f, "{}", oid.to_hex()),
+            DocId::String(s) => write!(f, "{}", s),
+            DocId::Int(n) => write!(f, "{}", n),
+            DocId::DateTime(date) => write!(f, "{}", date.timestamp_millis()),
+        }
+    }
+}
+
+impl DocId {
+    /// Every id whose text form is `s`, most specific first. Lets requests
+    /// that carry the id as a string address any id type.
+    pub fn candidates(s: &str) -> Vec<DocId> {
+        let mut ids = vec![];
+        if let Ok(uuid) = Uuid::from_str(s) {
+            ids.push(DocId::Uuid(uuid));
+        }
+        if let Ok(oid) = ObjectId::parse_str(s) {
+            ids.push(DocId::ObjectId(oid));
+        }
+        if let Ok(n) = s.parse::<i64>() {
+            ids.push(DocId::Int(n));
+        }
+        ids.push(DocId::String(s.to_string()));
+        ids
+    }
+}
+
+impl Serialize for DocId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Bson::from(self.clone()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DocId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Bson::deserialize(deserializer)?;
+        DocId::try_from(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How `_id` values are generated for documents inserted without one.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdStrategy {
+    /// Random UUIDs, stored as strings like ids generated by earlier versions.
+    #[default]
+    UuidV4,
+    /// Time-ordered UUIDs, stored as strings, which sort the same way.
+    UuidV7,
+    /// Time-ordered ULID strings.
+    Ulid,
+    ObjectId,
+    /// Documents must carry their own `_id`.
+    Client,
+}
+
+impl IdStrategy {
+    /// Returns a new id, or `None` when the client has to supply one.
+    pub fn generate(&self) -> Option<Bson> {
+        match self {
+            IdStrategy::UuidV4 => Some(Bson::String(Uuid::new_v4().to_string())),
+            IdStrategy::UuidV7 => Some(Bson::String(uuid_v7().to_string())),
+            IdStrategy::Ulid => Some(Bson::String(ulid())),
+            IdStrategy::ObjectId => Some(Bson::ObjectId(ObjectId::new())),
+            IdStrategy::Client => None,
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A v4 UUID with its leading 48 bits replaced by the current time.
+fn uuid_v7() -> Uuid {
+    let mut bytes = *Uuid::new_v4().as_bytes();
+    bytes[..6].copy_from_slice(&unix_millis().to_be_bytes()[2..]);
+    bytes[6] = 0x70 | (bytes[6] & 0x0f);
+    Uuid::from_bytes(bytes)
+}
+
+/// 48 bits of time followed by 80 random bits, in Crockford base32.
+fn ulid() -> String {
+    // The version and variant bits of a v4 UUID are in bytes 6 and 8.
+    let random = Uuid::new_v4();
+    let random = random.as_bytes();
+    let mut value: u128 = 0;
+    for byte in random[..6].iter().chain(&random[12..]) {
+        value = (value << 8) | *byte as u128;
+    }
+    value |= (unix_millis() as u128 & 0xffff_ffff_ffff) << 80;
+    (0..26)
+        .map(|i| CROCKFORD[((value >> (125 - 5 * i)) & 31) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_strategy_generates_uuid_strings() {
+        let id = IdStrategy::default().generate().unwrap();
+        let text = id.as_str().expect("a string _id");
+        assert_eq!(Uuid::from_str(text).unwrap().get_version_num(), 4);
+        assert_eq!(
+            DocId::try_from(&id).unwrap(),
+            DocId::String(text.to_string())
+        );
+    }
+
+    #[test]
+    fn uuid_v7_strings_parse_as_version_7() {
+        let id = IdStrategy::UuidV7.generate().unwrap();
+        let uuid = Uuid::from_str(id.as_str().expect("a string _id")).unwrap();
+        assert_eq!(uuid.get_version_num(), 7);
+    }
+
+    #[test]
+    fn uuid_v7_starts_with_the_current_time() {
+        let before = unix_millis();
+        let uuid = uuid_v7();
+        let after = unix_millis();
+        let mut millis = [0u8; 8];
+        millis[2..].copy_from_slice(&uuid.as_bytes()[..6]);
+        let millis = u64::from_be_bytes(millis);
+        assert!(before <= millis && millis <= after);
+        assert_eq!(uuid.as_bytes()[8] & 0xc0, 0x80);
+    }
+
+    #[test]
+    fn ulids_are_crockford_base32_and_sort_by_time() {
+        let first = ulid();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = ulid();
+        assert_eq!(first.len(), 26);
+        assert!(first.bytes().all(|c| CROCKFORD.contains(&c)));
+        assert!(first[..10] < second[..10]);
+    }
+
+    #[test]
+    fn object_id_and_client_strategies() {
+        assert!(matches!(
+            IdStrategy::ObjectId.generate(),
+            Some(Bson::ObjectId(_))
+        ));
+        assert_eq!(IdStrategy::Client.generate(), None);
+    }
+
+    #[test]
+    fn candidates_try_the_specific_types_first() {
+        let uuid = Uuid::new_v4();
+        assert_eq!(
+            DocId::candidates(&uuid.to_string()),
+            vec![DocId::Uuid(uuid), DocId::String(uuid.to_string())]
+        );
+        let oid = ObjectId::new();
+        assert_eq!(
+            DocId::candidates(&oid.to_hex()),
+            vec![DocId::ObjectId(oid), DocId::String(oid.to_hex())]
+        );
+        assert_eq!(
+            DocId::candidates("42"),
+            vec![DocId::Int(42), DocId::String("42".to_string())]
+        );
+        assert_eq!(
+            DocId::candidates("ada"),
+            vec![DocId::String("ada".to_string())]
+        );
+    }
+
+    #[test]
+    fn ids_round_trip_through_bson() {
+        let ids = vec![
+            DocId::Uuid(Uuid::new_v4()),
+            DocId::ObjectId(ObjectId::new()),
+            DocId::String("ada".to_string()),
+            DocId::Int(1 << 40),
+            DocId::DateTime(DateTime::now()),
+        ];
+        for id in ids {
+            assert_eq!(DocId::try_from(&Bson::from(id.clone())).unwrap(), id);
+            let doc = bson::doc! { "_id": id.clone() };
+            let back: bson::Document = bson::from_slice(&bson::to_vec(&doc).unwrap()).unwrap();
+            assert_eq!(DocId::try_from(back.get("_id").unwrap()).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn int32_and_int64_ids_are_equal() {
+        assert_eq!(
+            DocId::try_from(&Bson::Int32(7)).unwrap(),
+            DocId::try_from(&Bson::Int64(7)).unwrap()
+        );
+    }
+
+    #[test]
+    fn inexact_types_are_rejected() {
+        assert!(DocId::try_from(&Bson::Double(1.0)).is_err());
+        assert!(DocId::try_from(&Bson::Document(bson::doc! {})).is_err());
+        assert!(DocId::try_from(&Bson::Array(vec![])).is_err());
+    }
+}
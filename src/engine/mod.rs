@@ -1,222 +1,1099 @@
-use crate::config::EngineConfig;
-use bson::Document;
-use std::collections::BTreeMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+pub mod crypto;
+pub mod error;
+pub mod id;
+pub mod oplog;
+pub mod schema;
+
+use crate::collection::Collection;
+use crate::config::{AutoCreate, EngineConfig};
+use bson::{Bson, Document};
+use crypto::Cipher;
+use error::RusDbError;
+use id::{DocId, IdStrategy};
+use indexmap::IndexMap;
+use oplog::{OpKind, OpLog};
+use schema::{Schema, ValidationLevel};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 use tokio::fs;
-use tokio::sync::RwLock;
-use uuid::Uuid;
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Documents keyed by `_id`, kept in insertion (natural) order.
+pub type Documents = IndexMap<DocId, Document>;
+
+pub type RusDbCollection = Arc<RwLock<Documents>>;
+
+pub const DEFAULT_DATABASE: &str = "default";
+
+/// Seconds between TTL sweeps unless `[engine] ttl_interval` says otherwise.
+pub const DEFAULT_TTL_INTERVAL: u64 = 60;
+
+/// Database holding the internal `system.*` collections.
+pub const ADMIN_DATABASE: &str = "admin";
+
+/// Cache key: the database and collection names.
+type Namespace = (String, String);
+
+#[derive(Debug, Clone)]
+pub struct CollectionStats {
+    pub count: u64,
+    /// Serialized size of the cached documents, zero when not cached.
+    pub memory_size: u64,
+    pub disk_size: u64,
+    pub cached: bool,
+    pub last_access: Option<SystemTime>,
+}
+
+/// Per-collection settings chosen at creation, persisted next to the data file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollectionOptions {
+    /// Overrides `[engine] flush_time` for this collection, in minutes.
+    pub flush_time: Option<u32>,
+    pub validator: Option<Schema>,
+    pub validation_level: Option<ValidationLevel>,
+    pub ttl: Option<TtlOptions>,
+    pub capped: Option<CappedOptions>,
+    pub id_strategy: Option<IdStrategy>,
+}
+
+/// Limits for a capped collection. Once either is exceeded the oldest
+/// documents, in insertion order, are dropped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CappedOptions {
+    pub max_documents: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+impl CappedOptions {
+    /// How many of the oldest documents have to go for the collection to fit
+    /// its limits.
+    pub fn excess(&self, docs: &Documents) -> usize {
+        let mut count = match self.max_documents {
+            Some(max) => docs.len().saturating_sub(max as usize),
+            None => 0,
+        };
+        if let Some(max) = self.max_size {
+            let mut size: u64 = docs.values().skip(count).map(document_size).sum();
+            for doc in docs.values().skip(count) {
+                if size <= max {
+                    break;
+                }
+                size -= document_size(doc);
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Collections are stored as one BSON document with positional keys, like a
+/// BSON array, so ids of any type survive a round trip.
+fn documents_to_vec(docs: &Documents) -> Result<Vec<u8>, RusDbError> {
+    let mut stored = Document::new();
+    for (i, doc) in docs.values().enumerate() {
+        stored.insert(i.to_string(), doc.clone());
+    }
+    bson::to_vec(&stored).map_err(RusDbError::encode)
+}
+
+/// Rebuilds the keys from each document's `_id`, which also reads files
+/// written when collections were keyed by UUID strings.
+fn documents_from_slice(path: &Path, data: &[u8]) -> Result<Documents, RusDbError> {
+    let stored: Document = bson::from_slice(data).map_err(|e| RusDbError::corrupt(path, e))?;
+    let mut docs = Documents::with_capacity(stored.len());
+    for (key, value) in stored {
+        let doc = match value {
+            Bson::Document(doc) => doc,
+            _ => continue,
+        };
+        match doc.get("_id").map(DocId::try_from) {
+            Some(Ok(id)) => {
+                docs.insert(id, doc);
+            }
+            _ => warn!("Skipping stored document {} without a valid _id.", key),
+        }
+    }
+    Ok(docs)
+}
+
+/// Lowercases a collection name, or returns `None` if it could escape its
+/// database directory.
+pub fn collection_name(name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    if name.contains(['.', '/', '\\']) {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Like `collection_name`, with an empty name meaning the default database.
+pub fn database_name(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return Some(DEFAULT_DATABASE.to_string());
+    }
+    collection_name(name)
+}
+
+/// Encoded BSON size of a document in bytes.
+pub fn document_size(doc: &Document) -> u64 {
+    bson::to_vec(doc).map(|d| d.len() as u64).unwrap_or(0)
+}
+
+/// Expires documents once the date in `field` is more than `expire_after`
+/// seconds in the past. Documents without a date there never expire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtlOptions {
+    pub field: String,
+    pub expire_after: u64,
+}
+
+impl TtlOptions {
+    fn expired(&self, doc: &Document, now: SystemTime) -> bool {
+        // Like MongoDB, an array of dates expires with its earliest date.
+        let date = match doc.get(&self.field) {
+            Some(Bson::DateTime(date)) => Some(*date),
+            Some(Bson::Array(values)) => values
+                .iter()
+                .filter_map(|v| match v {
+                    Bson::DateTime(date) => Some(*date),
+                    _ => None,
+                })
+                .min(),
+            _ => None,
+        };
+        match date {
+            Some(date) => date.to_system_time() + Duration::from_secs(self.expire_after) <= now,
+            None => false,
+        }
+    }
+}
 
-pub type RusDbCollection = Arc<RwLock<BTreeMap<Uuid, Document>>>;
 struct RusCollection {
     pub last_access: SystemTime,
     pub flush_at: SystemTime,
     pub collection: RusDbCollection,
+    pub options: Arc<CollectionOptions>,
 }
 
 #[derive(Clone)]
 pub struct RusDbEngine {
-    cache: Arc<RwLock<BTreeMap<String, RusCollection>>>,
-    config: Arc<EngineConfig>,
+    cache: Arc<RwLock<BTreeMap<Namespace, RusCollection>>>,
+    /// Bumped under the cache lock whenever collections are dropped or
+    /// renamed, so a load that read the disk before then can tell its copy
+    /// is stale.
+    removals: Arc<AtomicU64>,
+    config: watch::Sender<EngineConfig>,
+    oplog: Arc<OpLog>,
+    cipher: Option<Arc<Cipher>>,
+    root: PathBuf,
+    shutdown: broadcast::Sender<()>,
+    /// The background flush, sync and TTL tasks.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
-impl Default for RusDbEngine {
-    fn default() -> Self {
-        Self {
-            cache: Arc::new(RwLock::new(BTreeMap::new())),
-            config: Arc::new(EngineConfig::default()),
+fn namespace(database: &str, name: &str) -> Namespace {
+    (database.to_string(), name.to_string())
+}
+
+/// Moves collections out of the flat `collections/` directory used before
+/// databases existed. Internal collections go to the admin database. Sealed
+/// files are bound to their path, so they are opened and sealed again for
+/// the new one; plaintext files are moved as they are.
+async fn migrate_collections(root: &Path, cipher: Option<&Cipher>) -> Result<(), RusDbError> {
+    let mut old = root.to_path_buf();
+    old.push("collections");
+    let mut entries = match fs::read_dir(&old).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    info!(
+        "Migrating collections into the {} database...",
+        DEFAULT_DATABASE
+    );
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file = entry.file_name();
+        let database = if file.to_string_lossy().starts_with("system.") {
+            ADMIN_DATABASE
+        } else {
+            DEFAULT_DATABASE
+        };
+        let mut to = root.to_path_buf();
+        to.push("databases");
+        to.push(database);
+        fs::create_dir_all(&to)
+            .await
+            .map_err(|e| RusDbError::io(&to, e))?;
+        to.push(&file);
+        let from = entry.path();
+        let data = fs::read(&from)
+            .await
+            .map_err(|e| RusDbError::io(&from, e))?;
+        if cipher.is_none() || !crypto::is_sealed(&data) {
+            fs::rename(&from, &to)
+                .await
+                .map_err(|e| RusDbError::io(&from, e))?;
+            continue;
         }
+        let data = crypto::decode_migrating(cipher, &crypto::file_aad(root, &from), data)?;
+        fs::write(
+            &to,
+            crypto::encode(cipher, &crypto::file_aad(root, &to), data),
+        )
+        .await
+        .map_err(|e| RusDbError::io(&to, e))?;
+        fs::remove_file(&from)
+            .await
+            .map_err(|e| RusDbError::io(&from, e))?;
     }
+    fs::remove_dir(&old)
+        .await
+        .map_err(|e| RusDbError::io(&old, e))
 }
 
-async fn dir_exists(dst: &str) -> bool {
-    if let Ok(meta) = fs::metadata(dst).await {
-        meta.is_dir()
-    } else {
-        false
+/// Sleeps for the interval `interval` picks from the engine configuration.
+/// The interval is measured from when the sleep began, so a reload shortens
+/// or extends a wait that is already underway.
+async fn sleep_interval(
+    changes: &mut watch::Receiver<EngineConfig>,
+    interval: impl Fn(&EngineConfig) -> Duration,
+) {
+    let start = Instant::now();
+    loop {
+        let deadline = start + interval(&changes.borrow_and_update());
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return,
+            changed = changes.changed() => {
+                if changed.is_err() {
+                    tokio::time::sleep_until(deadline).await;
+                    return;
+                }
+            }
+        }
     }
 }
 
-async fn col_exists_file(path: &PathBuf) -> Option<Vec<u8>> {
+async fn col_exists_file(path: &PathBuf) -> Result<Option<Vec<u8>>, RusDbError> {
     if let Ok(meta) = fs::metadata(path).await {
         if meta.is_file() {
-            Some(fs::read(path).await.unwrap())
+            fs::read(path)
+                .await
+                .map(Some)
+                .map_err(|e| RusDbError::io(path, e))
         } else {
-            None
+            Ok(None)
         }
     } else {
-        None
+        Ok(None)
     }
 }
 
 impl RusDbEngine {
-    pub async fn create(config: &EngineConfig) -> Arc<Self> {
-        let _dir = config.dir.clone().unwrap_or("./rusdb".to_string());
-        if !dir_exists(&_dir).await {
-            let mut p = std::env::current_dir().unwrap();
-            p.push(&_dir);
-            p.push("collections");
-            fs::create_dir_all(&p).await.unwrap();
-        }
+    pub async fn create(config: &EngineConfig) -> Result<Arc<Self>, RusDbError> {
+        let _dir = config.dir.clone().unwrap_or_else(|| "./rusdb".to_string());
+        let mut root = std::env::current_dir().map_err(|e| RusDbError::io(Path::new("."), e))?;
+        root.push(&_dir);
+        let mut databases = root.clone();
+        databases.push("databases");
+        fs::create_dir_all(&databases)
+            .await
+            .map_err(|e| RusDbError::io(&databases, e))?;
+        let cipher = match &config.key_file {
+            Some(path) => Some(Arc::new(Cipher::from_key_file(Path::new(path))?)),
+            None => None,
+        };
+        migrate_collections(&root, cipher.as_deref()).await?;
+        let mut oplog_path = root.clone();
+        oplog_path.push("oplog");
+        fs::create_dir_all(&oplog_path)
+            .await
+            .map_err(|e| RusDbError::io(&oplog_path, e))?;
+        oplog_path.push("oplog.bson");
+        let oplog = OpLog::open(
+            oplog_path.clone(),
+            crypto::file_aad(&root, &oplog_path),
+            config.oplog_retention.unwrap_or(oplog::DEFAULT_RETENTION),
+            cipher.clone(),
+        )
+        .await?;
 
         let engine = Arc::new(Self {
             cache: Arc::new(RwLock::new(BTreeMap::new())),
-            config: Arc::new(config.clone()),
+            removals: Arc::new(AtomicU64::new(0)),
+            config: watch::Sender::new(config.clone()),
+            oplog: Arc::new(oplog),
+            cipher,
+            root,
+            shutdown: broadcast::channel(1).0,
+            tasks: Arc::new(Mutex::new(vec![])),
         });
 
         let engine_inner = engine.clone();
         let engine_inner_2 = engine.clone();
+        let engine_inner_3 = engine.clone();
 
-        let cache_time = config.cache_time as u64;
-        let flush_time = config.flush_time as u64;
-
-        tokio::spawn(async move {
+        // The loops only stop between runs, so a flush or sync underway when
+        // shutdown begins completes before `shutdown` syncs one last time.
+        let mut shutdown = engine.shutdown.subscribe();
+        let flush_task = tokio::spawn(async move {
             let engine = engine_inner_2;
-            let shutdown_ = crate::SHUTDOWN_CHANNEL.0.clone();
-            let mut shutdown = shutdown_.subscribe();
-            let flush_task = tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(Duration::from_secs(60u64 * flush_time)).await;
-                    let engine = engine.clone();
-                    engine.flush_cache().await;
+            let mut changes = engine.config.subscribe();
+            loop {
+                tokio::select! {
+                    _ = sleep_interval(&mut changes, |config| {
+                        Duration::from_secs(60u64 * config.flush_time as u64)
+                    }) => {},
+                    _ = shutdown.recv() => break,
+                }
+                if let Err(e) = engine.flush_cache().await {
+                    error!("Unable to flush the cache: {}", e);
                 }
-            });
-            tokio::select! {
-                _ = flush_task => {},
-                _ = shutdown.recv() => {},
             }
             debug!("Cache timeout flushing task closed.");
-            drop(shutdown_);
         });
 
-        tokio::spawn(async move {
+        let mut shutdown = engine.shutdown.subscribe();
+        let ttl_task = tokio::spawn(async move {
+            let engine = engine_inner_3;
+            let mut changes = engine.config.subscribe();
+            loop {
+                tokio::select! {
+                    _ = sleep_interval(&mut changes, |config| {
+                        Duration::from_secs(
+                            config.ttl_interval.unwrap_or(DEFAULT_TTL_INTERVAL).max(1),
+                        )
+                    }) => {},
+                    _ = shutdown.recv() => break,
+                }
+                let removed = engine.expire_documents().await;
+                if removed > 0 {
+                    debug!("Expired {} documents.", removed);
+                }
+            }
+            debug!("TTL expiry task closed.");
+        });
+
+        let mut shutdown = engine.shutdown.subscribe();
+        let cache_task = tokio::spawn(async move {
             let engine = engine_inner;
-            let engine_ = engine.clone();
-            let shutdown_ = crate::SHUTDOWN_CHANNEL.0.clone();
-            let mut shutdown = shutdown_.subscribe();
-            let cache_loop = tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(Duration::from_secs(60u64 * cache_time)).await;
-                    let engine = engine.clone();
-                    engine.sync_cache().await;
+            let mut changes = engine.config.subscribe();
+            loop {
+                tokio::select! {
+                    _ = sleep_interval(&mut changes, |config| {
+                        Duration::from_secs(60u64 * config.cache_time as u64)
+                    }) => {},
+                    _ = shutdown.recv() => break,
+                }
+                if let Err(e) = engine.sync_cache().await {
+                    error!("Unable to sync the cache: {}", e);
                 }
-            });
-            tokio::select! {
-                _ = cache_loop => {},
-                _ = shutdown.recv() => {},
             }
             debug!("Cache task loop finished.");
-            engine_.sync_cache().await;
-            drop(shutdown);
-            drop(shutdown_);
         });
         engine
+            .tasks
+            .lock()
+            .unwrap()
+            .extend([flush_task, ttl_task, cache_task]);
+        Ok(engine)
+    }
+    /// Stops the background tasks, waits for any run of them still underway,
+    /// and writes every cached collection and the oplog to disk. The engine
+    /// stays usable, but nothing is flushed again until it is synced by hand.
+    pub async fn shutdown(&self) -> Result<(), RusDbError> {
+        let _ = self.shutdown.send(());
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(e) = task.await {
+                error!("A background task failed: {}", e);
+            }
+        }
+        self.sync_cache().await
+    }
+    /// Applies the settings of `config` that can change while running: the
+    /// cache, flush and TTL intervals, the oplog retention and `auto_create`.
+    /// The data directory and key file stay as the engine was opened with.
+    /// Cached collections pick up a new `flush_time` on their next access.
+    pub fn reconfigure(&self, config: &EngineConfig) {
+        self.oplog
+            .set_retention(config.oplog_retention.unwrap_or(oplog::DEFAULT_RETENTION));
+        self.config.send_if_modified(|current| {
+            let mut next = config.clone();
+            next.dir = current.dir.clone();
+            next.key_file = current.key_file.clone();
+            let changed = *current != next;
+            *current = next;
+            changed
+        });
+    }
+    pub fn oplog(&self) -> &OpLog {
+        &self.oplog
+    }
+    /// Seals the contents of the file at `path`, bound to that path.
+    fn encode(&self, path: &Path, data: Vec<u8>) -> Vec<u8> {
+        crypto::encode(
+            self.cipher.as_deref(),
+            &crypto::file_aad(&self.root, path),
+            data,
+        )
+    }
+    fn decode(&self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>, RusDbError> {
+        Ok(crypto::decode(
+            self.cipher.as_deref(),
+            &crypto::file_aad(&self.root, path),
+            data,
+        )?)
+    }
+    /// Moves a persisted file. Sealed files are bound to their path, so with
+    /// a key configured they are opened and sealed again for the new one.
+    async fn move_file(&self, from: &Path, to: &Path) -> Result<(), RusDbError> {
+        if self.cipher.is_none() {
+            return fs::rename(from, to)
+                .await
+                .map_err(|e| RusDbError::io(from, e));
+        }
+        let data = fs::read(from).await.map_err(|e| RusDbError::io(from, e))?;
+        let data = self.encode(to, self.decode(from, data)?);
+        fs::write(to, data)
+            .await
+            .map_err(|e| RusDbError::io(to, e))?;
+        fs::remove_file(from)
+            .await
+            .map_err(|e| RusDbError::io(from, e))
     }
     fn get_dir(&self) -> PathBuf {
-        let mut p = std::env::current_dir().unwrap();
-        p.push(&self.config.dir.clone().unwrap_or("./rusdb".to_string()));
-        p
+        self.root.clone()
+    }
+    fn database_path(&self, database: &str) -> PathBuf {
+        let mut path = self.get_dir();
+        path.push("databases");
+        path.push(database);
+        path
+    }
+    fn collection_path(&self, database: &str, name: &str) -> PathBuf {
+        let mut path = self.database_path(database);
+        path.push(format!("{}.bson", name));
+        path
+    }
+    async fn write_collection(&self, path: &Path, docs: &Documents) -> Result<(), RusDbError> {
+        let data = self.encode(path, documents_to_vec(docs)?);
+        fs::write(path, data)
+            .await
+            .map_err(|e| RusDbError::io(path, e))
     }
-    pub async fn flush_cache(&self) {
+    /// Writes collections past their flush time to disk and drops them from
+    /// the cache. A collection that fails to write stays cached, and the
+    /// first error is returned once every collection has been tried.
+    pub async fn flush_cache(&self) -> Result<(), RusDbError> {
         let mut lock = self.cache.write().await;
-        let mut entries: Vec<String> = vec![];
+        let mut entries: Vec<Namespace> = vec![];
+        let mut result = Ok(());
         let now = SystemTime::now();
         for (k, v) in &*lock {
-            if &now >= &v.flush_at {
+            if now >= v.flush_at {
                 // flush from the cache.
-                debug!("Flushing {} from the cache...", k);
-                let mut path = self.get_dir();
-                path.push("collections");
-                path.push(format!("{}.bson", k));
+                debug!("Flushing {}.{} from the cache...", k.0, k.1);
+                let path = self.collection_path(&k.0, &k.1);
                 let ilock = v.collection.read().await;
-                let data = bson::to_vec(&*ilock).unwrap();
-                fs::write(&path, &data).await.unwrap();
-                entries.push(k.clone());
+                match self.write_collection(&path, &ilock).await {
+                    Ok(()) => entries.push(k.clone()),
+                    Err(e) => {
+                        error!("Unable to flush {}.{}: {}", k.0, k.1, e);
+                        result = result.and(Err(e));
+                    }
+                }
             }
         }
         for entry in entries.drain(..) {
             (*lock).remove(&entry);
         }
+        result
     }
-    pub async fn sync_cache(&self) {
+    /// Writes every cached collection to disk and syncs the oplog. Like
+    /// `flush_cache`, it keeps going past failures and returns the first.
+    pub async fn sync_cache(&self) -> Result<(), RusDbError> {
         let lock = self.cache.read().await;
-        if (*lock).len() > 0 {
-            for (k, v) in &*lock {
-                let v = &v.collection;
-                let mut path = self.get_dir();
-                path.push("collections");
-                path.push(format!("{}.bson", k));
-                let ilock = v.read().await;
-                let data = bson::to_vec(&*ilock).unwrap();
-                fs::write(&path, &data).await.unwrap();
+        let mut result = Ok(());
+        for (k, v) in &*lock {
+            let v = &v.collection;
+            let path = self.collection_path(&k.0, &k.1);
+            let ilock = v.read().await;
+            if let Err(e) = self.write_collection(&path, &ilock).await {
+                error!("Unable to sync {}.{}: {}", k.0, k.1, e);
+                result = result.and(Err(e));
+            }
+        }
+        result.and(self.oplog.sync().await)
+    }
+    fn options_path(&self, database: &str, name: &str) -> PathBuf {
+        let mut path = self.database_path(database);
+        path.push(format!("{}.meta.bson", name));
+        path
+    }
+    async fn read_options(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<CollectionOptions, RusDbError> {
+        let path = self.options_path(database, name);
+        match col_exists_file(&path).await? {
+            Some(data) => bson::from_slice(&self.decode(&path, data)?)
+                .map_err(|e| RusDbError::corrupt(&path, e)),
+            None => Ok(CollectionOptions::default()),
+        }
+    }
+    fn flush_at(&self, options: &CollectionOptions, now: SystemTime) -> SystemTime {
+        let flush_time = options
+            .flush_time
+            .unwrap_or(self.config.borrow().flush_time);
+        // A time past what SystemTime can hold flushes on the next tick.
+        now.checked_add(Duration::from_secs(flush_time as u64 * 60u64))
+            .unwrap_or(now)
+    }
+    fn cache_entry(
+        &self,
+        collection: RusDbCollection,
+        options: CollectionOptions,
+    ) -> RusCollection {
+        let now = SystemTime::now();
+        RusCollection {
+            collection,
+            last_access: now,
+            flush_at: self.flush_at(&options, now),
+            options: Arc::new(options),
+        }
+    }
+    /// A handle on `database.name` whose documents are read and written as
+    /// `T`. Nothing is loaded or created until the handle is used.
+    pub fn collection<T>(
+        self: &Arc<Self>,
+        database: &str,
+        name: &str,
+    ) -> Result<Collection<T>, RusDbError> {
+        let database = database_name(database).ok_or_else(|| {
+            RusDbError::Invalid("Database name contains invalid characters.".into())
+        })?;
+        let name = collection_name(name)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| {
+                RusDbError::Invalid("Collection name contains invalid characters.".into())
+            })?;
+        Ok(Collection::new(self.clone(), database, name))
+    }
+    /// Returns an existing collection, loading it into the cache if needed.
+    /// Missing collections are never created here.
+    pub async fn get_collection(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<Option<RusDbCollection>, RusDbError> {
+        self.load_collection(database, name, true).await
+    }
+    /// Background tasks load without `touch` so they never keep a collection
+    /// in the cache by themselves.
+    async fn load_collection(
+        &self,
+        database: &str,
+        name: &str,
+        touch: bool,
+    ) -> Result<Option<RusDbCollection>, RusDbError> {
+        debug!("Attempting to load collection: {}.{}", database, name);
+        let key = namespace(database, name);
+        loop {
+            let removals = {
+                let mut lock = self.cache.write().await;
+                if let Some(col) = (*lock).get_mut(&key) {
+                    debug!("Collection was cached.");
+                    if !touch {
+                        return Ok(Some(col.collection.clone()));
+                    }
+                    let now = SystemTime::now();
+                    col.last_access = now;
+                    col.flush_at = self.flush_at(&col.options, now);
+                    debug!("Flushing the cache at {:?}", &col.flush_at);
+                    return Ok(Some(col.collection.clone()));
+                }
+                self.removals.load(Ordering::SeqCst)
+            };
+            debug!("Collection is not cached.");
+            let path = self.collection_path(database, name);
+            let data = match col_exists_file(&path).await? {
+                Some(data) => data,
+                None => return Ok(None),
+            };
+            debug!("Loaded collection from disk.");
+            let btree: RusDbCollection = Arc::new(RwLock::new(documents_from_slice(
+                &path,
+                &self.decode(&path, data)?,
+            )?));
+            let options = self.read_options(database, name).await?;
+            let mut lock = self.cache.write().await;
+            // A drop or rename meanwhile may have removed or replaced the
+            // file, and caching this copy would bring it back.
+            if self.removals.load(Ordering::SeqCst) != removals {
+                debug!("Collections changed while loading, loading again.");
+                continue;
             }
+            // Another request may have loaded it meanwhile; keep the first copy.
+            let col = (*lock)
+                .entry(key)
+                .or_insert_with(|| self.cache_entry(btree, options));
+            return Ok(Some(col.collection.clone()));
         }
     }
-    pub async fn get_collection(&self, name: &str) -> Option<RusDbCollection> {
-        debug!("Attempting to load collection: {}", name);
-        let is_cached = {
+    /// Creates an empty collection with the given options and writes it to
+    /// disk straight away. The database is created along with it.
+    pub async fn create_collection(
+        &self,
+        database: &str,
+        name: &str,
+        options: CollectionOptions,
+    ) -> Result<RusDbCollection, RusDbError> {
+        let key = namespace(database, name);
+        let mut lock = self.cache.write().await;
+        let path = self.collection_path(database, name);
+        if (*lock).contains_key(&key) || fs::metadata(&path).await.is_ok() {
+            return Err(RusDbError::CollectionExists(name.to_string()));
+        }
+        debug!("Writing empty collection {}.{} to disk.", database, name);
+        let dir = self.database_path(database);
+        fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| RusDbError::io(&dir, e))?;
+        let btree = Documents::new();
+        self.write_collection(&path, &btree).await?;
+        let options_path = self.options_path(database, name);
+        let data = bson::to_vec(&options).map_err(RusDbError::encode)?;
+        fs::write(&options_path, self.encode(&options_path, data))
+            .await
+            .map_err(|e| RusDbError::io(&options_path, e))?;
+        let btree = Arc::new(RwLock::new(btree));
+        (*lock).insert(key, self.cache_entry(btree.clone(), options));
+        Ok(btree)
+    }
+    /// Returns the collection, creating it with default options if missing.
+    /// Used for internal collections regardless of the `auto_create` policy.
+    pub async fn ensure_collection(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<RusDbCollection, RusDbError> {
+        loop {
+            if let Some(col) = self.get_collection(database, name).await? {
+                return Ok(col);
+            }
+            match self
+                .create_collection(database, name, CollectionOptions::default())
+                .await
+            {
+                Ok(col) => return Ok(col),
+                // Created by another request meanwhile; load that one.
+                Err(RusDbError::CollectionExists(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Looks up a collection for a request, creating it when the configured
+    /// `auto_create` policy allows it for this kind of access.
+    pub async fn auto_collection(
+        &self,
+        database: &str,
+        name: &str,
+        write: bool,
+    ) -> Result<Option<RusDbCollection>, RusDbError> {
+        let create = match self.config.borrow().auto_create.unwrap_or_default() {
+            AutoCreate::Always => true,
+            AutoCreate::OnWriteOnly => write,
+            AutoCreate::Never => false,
+        };
+        if create {
+            self.ensure_collection(database, name).await.map(Some)
+        } else {
+            self.get_collection(database, name).await
+        }
+    }
+    pub async fn collection_options(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<Option<Arc<CollectionOptions>>, RusDbError> {
+        {
             let lock = self.cache.read().await;
-            (*lock).contains_key(name)
+            if let Some(col) = (*lock).get(&namespace(database, name)) {
+                return Ok(Some(col.options.clone()));
+            }
+        }
+        if fs::metadata(self.collection_path(database, name))
+            .await
+            .is_err()
+        {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(self.read_options(database, name).await?)))
+    }
+    /// Removes expired documents from every collection with TTL options,
+    /// recording each removal in the oplog. Returns how many were removed.
+    /// Collections that fail are logged and skipped.
+    pub async fn expire_documents(&self) -> usize {
+        let mut removed = 0;
+        for database in self.list_databases().await {
+            for name in self.list_collections(&database).await {
+                match self.expire_collection(&database, &name).await {
+                    Ok(count) => removed += count,
+                    Err(e) => error!("Unable to expire documents in {}.{}: {}", database, name, e),
+                }
+            }
+        }
+        removed
+    }
+    async fn expire_collection(&self, database: &str, name: &str) -> Result<usize, RusDbError> {
+        let ttl = match self.collection_options(database, name).await? {
+            Some(options) => match &options.ttl {
+                Some(ttl) => ttl.clone(),
+                None => return Ok(0),
+            },
+            None => return Ok(0),
         };
-        if !is_cached {
-            debug!("Collection is not cached.");
-            let mut path = self.get_dir();
-            path.push("collections");
-            path.push(format!("{}.bson", &name));
-            if let Some(data) = col_exists_file(&path).await {
-                debug!("Loaded collection from disk.");
-                let btree: RusDbCollection = Arc::new(RwLock::new(
-                    bson::from_slice::<BTreeMap<Uuid, Document>>(&data).unwrap(),
-                ));
-                let now = SystemTime::now();
-                let icol = RusCollection {
-                    collection: btree.clone(),
-                    last_access: now.clone(),
-                    flush_at: now
-                        .checked_add(Duration::from_secs(self.config.flush_time as u64 * 60u64))
-                        .unwrap(),
-                };
-                {
-                    let mut lock = self.cache.write().await;
-                    (*lock).insert(name.to_string(), icol);
+        let col = match self.load_collection(database, name, false).await? {
+            Some(col) => col,
+            None => return Ok(0),
+        };
+        let now = SystemTime::now();
+        let mut lock = col.write().await;
+        let expired: Vec<DocId> = lock
+            .iter()
+            .filter(|(_, doc)| ttl.expired(doc, now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut removed = HashSet::with_capacity(expired.len());
+        let mut result = Ok(());
+        for id in expired {
+            if let Err(e) = self
+                .oplog
+                .append(OpKind::Remove, database, name, id.clone(), None)
+                .await
+            {
+                result = Err(e);
+                break;
+            }
+            removed.insert(id);
+        }
+        lock.retain(|id, _| !removed.contains(id));
+        result.map(|_| removed.len())
+    }
+    /// Lists databases that hold at least one collection, cached or on disk.
+    pub async fn list_databases(&self) -> Vec<String> {
+        let mut names: Vec<String> = {
+            let lock = self.cache.read().await;
+            lock.keys().map(|(database, _)| database.clone()).collect()
+        };
+        let mut path = self.get_dir();
+        path.push("databases");
+        if let Ok(mut entries) = fs::read_dir(&path).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
                 }
-                Some(btree)
-            } else {
-                // Doesn't exist, create it.
-                debug!("Writing empty collection to disk.");
-                let btree: BTreeMap<Uuid, Document> = BTreeMap::new();
-                let data = bson::to_vec(&btree).unwrap();
-                fs::write(&path, &data).await.unwrap();
-                let btree = Arc::new(RwLock::new(btree));
-                let now = SystemTime::now();
-                let icol = RusCollection {
-                    collection: btree.clone(),
-                    last_access: now.clone(),
-                    flush_at: now
-                        .checked_add(Duration::from_secs(self.config.flush_time as u64 * 60u64))
-                        .unwrap(),
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+    /// Removes a database and every collection in it from the cache and disk.
+    pub async fn drop_database(&self, database: &str) -> bool {
+        let mut lock = self.cache.write().await;
+        self.removals.fetch_add(1, Ordering::SeqCst);
+        let before = (*lock).len();
+        (*lock).retain(|(db, _), _| db != database);
+        let cached = (*lock).len() != before;
+        let on_disk = fs::remove_dir_all(self.database_path(database))
+            .await
+            .is_ok();
+        cached || on_disk
+    }
+    /// Lists user collections in a database, both cached and on disk.
+    /// Internal collections (which contain a `.`) are not included.
+    pub async fn list_collections(&self, database: &str) -> Vec<String> {
+        let mut names: Vec<String> = {
+            let lock = self.cache.read().await;
+            lock.keys()
+                .filter(|(db, _)| db == database)
+                .map(|(_, name)| name.clone())
+                .collect()
+        };
+        if let Ok(mut entries) = fs::read_dir(self.database_path(database)).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let file = entry.file_name();
+                if let Some(name) = file.to_str().and_then(|f| f.strip_suffix(".bson")) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.retain(|name| !name.contains('.'));
+        names.sort();
+        names.dedup();
+        names
+    }
+    /// Removes a collection from the cache and disk. The cache lock is held
+    /// throughout so the sync and flush tasks cannot write it back.
+    pub async fn drop_collection(&self, database: &str, name: &str) -> bool {
+        let mut lock = self.cache.write().await;
+        self.removals.fetch_add(1, Ordering::SeqCst);
+        let cached = (*lock).remove(&namespace(database, name)).is_some();
+        let on_disk = fs::remove_file(self.collection_path(database, name))
+            .await
+            .is_ok();
+        let _ = fs::remove_file(self.options_path(database, name)).await;
+        cached || on_disk
+    }
+    pub async fn rename_collection(
+        &self,
+        database: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<(), RusDbError> {
+        let mut lock = self.cache.write().await;
+        let from_path = self.collection_path(database, from);
+        let to_path = self.collection_path(database, to);
+        if (*lock).contains_key(&namespace(database, to)) || fs::metadata(&to_path).await.is_ok() {
+            return Err(RusDbError::CollectionExists(to.to_string()));
+        }
+        let on_disk = fs::metadata(&from_path).await.is_ok();
+        if !(*lock).contains_key(&namespace(database, from)) && !on_disk {
+            return Err(RusDbError::CollectionNotFound(from.to_string()));
+        }
+        self.removals.fetch_add(1, Ordering::SeqCst);
+        if on_disk {
+            self.move_file(&from_path, &to_path).await?;
+        }
+        let cached = (*lock).remove(&namespace(database, from));
+        let options_from = self.options_path(database, from);
+        if fs::metadata(&options_from).await.is_ok() {
+            self.move_file(&options_from, &self.options_path(database, to))
+                .await?;
+        }
+        if let Some(col) = cached {
+            (*lock).insert(namespace(database, to), col);
+        }
+        Ok(())
+    }
+    /// Reports on a collection without pulling it into the cache.
+    pub async fn collection_stats(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<Option<CollectionStats>, RusDbError> {
+        let path = self.collection_path(database, name);
+        let disk_size = fs::metadata(&path).await.map(|m| m.len()).ok();
+        let cached = {
+            let lock = self.cache.read().await;
+            (*lock)
+                .get(&namespace(database, name))
+                .map(|col| (col.collection.clone(), col.last_access))
+        };
+        match cached {
+            Some((col, last_access)) => {
+                let lock = col.read().await;
+                let memory_size = lock.values().map(document_size).sum();
+                Ok(Some(CollectionStats {
+                    count: lock.len() as u64,
+                    memory_size,
+                    disk_size: disk_size.unwrap_or(0),
+                    cached: true,
+                    last_access: Some(last_access),
+                }))
+            }
+            None => {
+                let data = match col_exists_file(&path).await? {
+                    Some(data) => data,
+                    None => return Ok(None),
                 };
-                {
-                    let mut lock = self.cache.write().await;
-                    (*lock).insert(name.to_string(), icol);
+                let docs = bson::from_slice::<Document>(&self.decode(&path, data)?)
+                    .map_err(|e| RusDbError::corrupt(&path, e))?;
+                Ok(Some(CollectionStats {
+                    count: docs.len() as u64,
+                    memory_size: 0,
+                    disk_size: disk_size.unwrap_or(0),
+                    cached: false,
+                    last_access: None,
+                }))
+            }
+        }
+    }
+}
+
+/// The step of a key rotation that failed.
+#[derive(Debug)]
+pub enum RotateError {
+    /// A file could not be read or decoded. Nothing was written.
+    Read(RusDbError),
+    /// A re-encoded copy could not be written. The copies written so far were
+    /// removed and no file was replaced.
+    Stage(RusDbError),
+    /// A file could not be replaced by its re-encoded copy. The first
+    /// `replaced` files use the new key; the others still have their copy
+    /// next to them as `.bson.tmp`.
+    Replace {
+        source: RusDbError,
+        replaced: usize,
+        total: usize,
+    },
+}
+
+impl std::fmt::Display for RotateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RotateError::Read(e) => write!(f, "{}; nothing was written", e),
+            RotateError::Stage(e) => write!(
+                f,
+                "unable to write a re-encoded copy: {}; nothing was replaced",
+                e
+            ),
+            RotateError::Replace {
+                source,
+                replaced,
+                total,
+            } => write!(
+                f,
+                "{}; {} of {} files were replaced, the rest have their re-encoded copy next to them as .bson.tmp",
+                source, replaced, total
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RotateError {}
+
+/// Re-encodes every persisted file under `dir` from the `old` key to the `new`
+/// one. Either side may be `None` for plaintext. The server must not be running.
+///
+/// Every file, the oplog included, is decoded and written next to itself as
+/// `.bson.tmp` before any of them is replaced, so a wrong `old` key or a full
+/// disk leaves the directory untouched.
+pub async fn rotate_key(
+    dir: &Path,
+    old: Option<&Cipher>,
+    new: Option<&Cipher>,
+) -> Result<(usize, usize), RotateError> {
+    // Data directories that predate databases still use a flat `collections/`.
+    let mut dirs: Vec<PathBuf> = vec![dir.join("collections")];
+    if let Ok(mut entries) = fs::read_dir(dir.join("databases")).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            dirs.push(entry.path());
+        }
+    }
+    let mut files: Vec<(PathBuf, Vec<u8>)> = vec![];
+    for path in dirs {
+        if let Ok(mut entries) = fs::read_dir(&path).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let file = entry.path();
+                if file.extension().and_then(|e| e.to_str()) != Some("bson") {
+                    continue;
                 }
-                Some(btree)
+                let data = fs::read(&file)
+                    .await
+                    .map_err(|e| RotateError::Read(RusDbError::io(&file, e)))?;
+                let aad = crypto::file_aad(dir, &file);
+                let data = crypto::decode_migrating(old, &aad, data)
+                    .map_err(|e| RotateError::Read(e.into()))?;
+                files.push((file, crypto::encode(new, &aad, data)));
             }
-        } else {
-            debug!("Collection was cached.");
-            let mut lock = self.cache.write().await;
-            if let Some(col) = (*lock).get_mut(name) {
-                let now = SystemTime::now();
-                col.last_access = now.clone();
-                col.flush_at = now
-                    .checked_add(Duration::from_secs(self.config.flush_time as u64 * 60u64))
-                    .unwrap();
-                debug!("Flushing the cache at {:?}", &col.flush_at);
-                Some(col.collection.clone())
-            } else {
-                None
+        }
+    }
+    let collections = files.len();
+    let mut path = dir.to_path_buf();
+    path.push("oplog");
+    path.push("oplog.bson");
+    let entries = match fs::read(&path).await {
+        Ok(data) => {
+            let aad = crypto::file_aad(dir, &path);
+            let (data, entries) =
+                oplog::reencode(&data, &aad, old, new).map_err(RotateError::Read)?;
+            files.push((path, data));
+            entries
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(RotateError::Read(RusDbError::io(&path, e))),
+    };
+    let mut staged: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(files.len());
+    for (file, data) in files {
+        let mut tmp = file.clone();
+        tmp.set_extension("bson.tmp");
+        if let Err(e) = fs::write(&tmp, data).await {
+            let _ = fs::remove_file(&tmp).await;
+            for (tmp, _) in &staged {
+                let _ = fs::remove_file(tmp).await;
             }
+            return Err(RotateError::Stage(RusDbError::io(&tmp, e)));
         }
+        staged.push((tmp, file));
+    }
+    let total = staged.len();
+    for (replaced, (tmp, file)) in staged.iter().enumerate() {
+        fs::rename(tmp, file)
+            .await
+            .map_err(|e| RotateError::Replace {
+                source: RusDbError::io(file, e),
+                replaced,
+                total,
+            })?;
+    }
+    Ok((collections, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn documents(n: i64) -> Documents {
+        (0..n)
+            .map(|i| (DocId::Int(i), doc! { "_id": i, "pad": "x".repeat(10) }))
+            .collect()
+    }
+
+    #[test]
+    fn capped_excess_counts_documents_over_the_limit() {
+        let capped = CappedOptions {
+            max_documents: Some(3),
+            max_size: None,
+        };
+        assert_eq!(capped.excess(&documents(2)), 0);
+        assert_eq!(capped.excess(&documents(5)), 2);
+    }
+
+    #[test]
+    fn capped_excess_drops_the_oldest_documents_to_fit_the_size() {
+        let docs = documents(4);
+        let size = document_size(&docs[0]);
+        let capped = CappedOptions {
+            max_documents: None,
+            max_size: Some(size * 2 + 1),
+        };
+        let count = capped.excess(&docs);
+        assert_eq!(count, 2);
+        let mut docs = docs;
+        docs.drain(..count);
+        let kept: Vec<&DocId> = docs.keys().collect();
+        assert_eq!(kept, vec![&DocId::Int(2), &DocId::Int(3)]);
+    }
+
+    #[test]
+    fn capped_excess_applies_both_limits() {
+        let docs = documents(6);
+        let size = document_size(&docs[0]);
+        let capped = CappedOptions {
+            max_documents: Some(4),
+            max_size: Some(size * 3),
+        };
+        assert_eq!(capped.excess(&docs), 3);
     }
 }
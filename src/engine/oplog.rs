@@ -0,0 +1,365 @@
+use super::crypto::{self, Cipher, CryptoError};
+use super::error::RusDbError;
+use super::id::DocId;
+use bson::{DateTime, Document};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, Mutex};
+
+pub const DEFAULT_RETENTION: u64 = 10000;
+
+const LIVE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpKind {
+    Insert,
+    Update,
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpLogEntry {
+    pub seq: u64,
+    pub ts: DateTime,
+    pub op: OpKind,
+    #[serde(default = "default_database")]
+    pub database: String,
+    pub collection: String,
+    pub id: DocId,
+    pub document: Option<Document>,
+}
+
+/// Entries written before databases existed belong to the default one.
+fn default_database() -> String {
+    super::DEFAULT_DATABASE.to_string()
+}
+
+#[derive(Debug)]
+pub enum ResumeError {
+    /// The requested position has already been dropped from the log.
+    Truncated { requested: u64, oldest: u64 },
+    /// The requested position has not been written yet.
+    Ahead { requested: u64, latest: u64 },
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResumeError::Truncated { requested, oldest } => write!(
+                f,
+                "Sequence {} has been truncated from the oplog; the oldest retained sequence is {}.",
+                requested, oldest
+            ),
+            ResumeError::Ahead { requested, latest } => write!(
+                f,
+                "Sequence {} has not been reached; the latest sequence is {}.",
+                requested, latest
+            ),
+        }
+    }
+}
+
+struct OpLogState {
+    entries: VecDeque<OpLogEntry>,
+    next_seq: u64,
+    file: File,
+    records: u64,
+}
+
+/// Capped, append-only log of every write applied to the engine.
+///
+/// Entries are appended to disk as they happen and the most recent `retention`
+/// entries are kept in memory so watchers can resume from any retained sequence.
+pub struct OpLog {
+    path: PathBuf,
+    retention: AtomicU64,
+    cipher: Option<Arc<Cipher>>,
+    /// Associated data of sealed records, see `crypto::file_aad`.
+    aad: Vec<u8>,
+    state: Mutex<OpLogState>,
+    sender: broadcast::Sender<OpLogEntry>,
+}
+
+/// Encodes one record. Sealed records are framed with a little-endian length
+/// since the ciphertext no longer carries the BSON document length.
+fn encode_record(
+    entry: &OpLogEntry,
+    cipher: Option<&Cipher>,
+    aad: &[u8],
+) -> Result<Vec<u8>, RusDbError> {
+    let data = bson::to_vec(entry).map_err(RusDbError::encode)?;
+    Ok(match cipher {
+        Some(cipher) => {
+            let sealed = cipher.seal(&data, aad);
+            let mut out = (sealed.len() as u32).to_le_bytes().to_vec();
+            out.extend(sealed);
+            out
+        }
+        None => data,
+    })
+}
+
+/// Decodes every complete record in `data`, stopping at the first corrupt or
+/// partial one. The flag reports whether the whole buffer was consumed.
+/// Plaintext records are only accepted without a cipher, or when
+/// `migrating` for `rotate-key`.
+fn decode_records(
+    data: &[u8],
+    cipher: Option<&Cipher>,
+    aad: &[u8],
+    migrating: bool,
+) -> Result<(VecDeque<OpLogEntry>, bool), CryptoError> {
+    let mut entries: VecDeque<OpLogEntry> = VecDeque::new();
+    let mut pos = 0usize;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if rest.len() >= 8 && crypto::is_sealed(&rest[4..]) {
+            let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if rest.len() < 4 + len {
+                return Ok((entries, false));
+            }
+            let sealed = rest[4..4 + len].to_vec();
+            let plain = if migrating {
+                crypto::decode_migrating(cipher, aad, sealed)?
+            } else {
+                crypto::decode(cipher, aad, sealed)?
+            };
+            match bson::from_slice::<OpLogEntry>(&plain) {
+                Ok(entry) => entries.push_back(entry),
+                Err(_) => return Ok((entries, false)),
+            }
+            pos += 4 + len;
+        } else {
+            if cipher.is_some() && !migrating {
+                // Too short to be a whole sealed record: torn by a crash.
+                if rest.len() < 8 {
+                    return Ok((entries, false));
+                }
+                return Err(CryptoError::Plaintext);
+            }
+            let mut cursor = Cursor::new(rest);
+            let entry = Document::from_reader(&mut cursor)
+                .ok()
+                .and_then(|doc| bson::from_document::<OpLogEntry>(doc).ok());
+            match entry {
+                Some(entry) => entries.push_back(entry),
+                None => return Ok((entries, false)),
+            }
+            pos += cursor.position() as usize;
+        }
+    }
+    Ok((entries, true))
+}
+
+fn encode_records(
+    entries: &VecDeque<OpLogEntry>,
+    cipher: Option<&Cipher>,
+    aad: &[u8],
+) -> Result<Vec<u8>, RusDbError> {
+    let mut data: Vec<u8> = vec![];
+    for entry in entries {
+        data.extend(encode_record(entry, cipher, aad)?);
+    }
+    Ok(data)
+}
+
+async fn rewrite(
+    path: &Path,
+    entries: &VecDeque<OpLogEntry>,
+    cipher: Option<&Cipher>,
+    aad: &[u8],
+) -> Result<File, RusDbError> {
+    let mut tmp = path.to_path_buf();
+    tmp.set_extension("bson.tmp");
+    let data = encode_records(entries, cipher, aad)?;
+    fs::write(&tmp, data)
+        .await
+        .map_err(|e| RusDbError::io(&tmp, e))?;
+    fs::rename(&tmp, path)
+        .await
+        .map_err(|e| RusDbError::io(path, e))?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .await
+        .map_err(|e| RusDbError::io(path, e))
+}
+
+/// Re-encodes the contents of an oplog file from one key (or plaintext) to
+/// another, returning the new contents and the number of entries.
+pub fn reencode(
+    data: &[u8],
+    aad: &[u8],
+    old: Option<&Cipher>,
+    new: Option<&Cipher>,
+) -> Result<(Vec<u8>, usize), RusDbError> {
+    let (entries, complete) = decode_records(data, old, aad, true)?;
+    if !complete {
+        warn!("Discarding a corrupt or partial oplog record.");
+    }
+    Ok((encode_records(&entries, new, aad)?, entries.len()))
+}
+
+impl OpLog {
+    pub async fn open(
+        path: PathBuf,
+        aad: Vec<u8>,
+        retention: u64,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self, RusDbError> {
+        let retention = retention.max(1);
+        let mut entries: VecDeque<OpLogEntry> = VecDeque::new();
+        if let Ok(data) = fs::read(&path).await {
+            let (loaded, complete) = decode_records(&data, cipher.as_deref(), &aad, false)?;
+            if !complete {
+                warn!("Discarding a corrupt or partial oplog record.");
+            }
+            entries = loaded;
+        }
+        while entries.len() as u64 > retention {
+            entries.pop_front();
+        }
+        let next_seq = entries.back().map(|e| e.seq + 1).unwrap_or(1);
+        debug!(
+            "Loaded {} oplog entries, next sequence is {}.",
+            entries.len(),
+            next_seq
+        );
+        // Compact on open so a partial trailing record never precedes new appends.
+        let file = rewrite(&path, &entries, cipher.as_deref(), &aad).await?;
+        let (sender, _) = broadcast::channel(LIVE_CAPACITY);
+        Ok(Self {
+            path,
+            retention: AtomicU64::new(retention),
+            cipher,
+            aad,
+            state: Mutex::new(OpLogState {
+                records: entries.len() as u64,
+                entries,
+                next_seq,
+                file,
+            }),
+            sender,
+        })
+    }
+    /// Changes how many entries are kept. A smaller retention trims the
+    /// backlog with the next append.
+    pub fn set_retention(&self, retention: u64) {
+        self.retention.store(retention.max(1), Ordering::Relaxed);
+    }
+    /// Writes an entry to disk before publishing it. Nothing is recorded if
+    /// the write fails.
+    pub async fn append(
+        &self,
+        op: OpKind,
+        database: &str,
+        collection: &str,
+        id: DocId,
+        document: Option<Document>,
+    ) -> Result<u64, RusDbError> {
+        let mut state = self.state.lock().await;
+        let entry = OpLogEntry {
+            seq: state.next_seq,
+            ts: DateTime::now(),
+            op,
+            database: database.to_string(),
+            collection: collection.to_string(),
+            id,
+            document,
+        };
+        let data = encode_record(&entry, self.cipher.as_deref(), &self.aad)?;
+        state
+            .file
+            .write_all(&data)
+            .await
+            .map_err(|e| RusDbError::io(&self.path, e))?;
+        state
+            .file
+            .flush()
+            .await
+            .map_err(|e| RusDbError::io(&self.path, e))?;
+        state.next_seq += 1;
+        state.records += 1;
+        state.entries.push_back(entry.clone());
+        let retention = self.retention.load(Ordering::Relaxed);
+        while state.entries.len() as u64 > retention {
+            state.entries.pop_front();
+        }
+        if state.records >= retention * 2 {
+            debug!("Compacting the oplog...");
+            match rewrite(
+                &self.path,
+                &state.entries,
+                self.cipher.as_deref(),
+                &self.aad,
+            )
+            .await
+            {
+                Ok(file) => {
+                    state.file = file;
+                    state.records = state.entries.len() as u64;
+                }
+                // The entry is already on disk; compaction is retried next time.
+                Err(e) => error!("Unable to compact the oplog: {}", e),
+            }
+        }
+        // Nobody watching is not an error.
+        let _ = self.sender.send(entry.clone());
+        Ok(entry.seq)
+    }
+    /// Returns every retained entry after `after` along with a receiver for
+    /// entries appended from this point on. Passing `None` only subscribes.
+    pub async fn resume(
+        &self,
+        after: Option<u64>,
+    ) -> Result<(Vec<OpLogEntry>, broadcast::Receiver<OpLogEntry>), ResumeError> {
+        let state = self.state.lock().await;
+        let receiver = self.sender.subscribe();
+        let after = match after {
+            Some(after) => after,
+            None => return Ok((vec![], receiver)),
+        };
+        let latest = state.next_seq - 1;
+        if after > latest {
+            return Err(ResumeError::Ahead {
+                requested: after,
+                latest,
+            });
+        }
+        let oldest = state
+            .entries
+            .front()
+            .map(|e| e.seq)
+            .unwrap_or(state.next_seq);
+        if after + 1 < oldest {
+            return Err(ResumeError::Truncated {
+                requested: after,
+                oldest,
+            });
+        }
+        let backlog = state
+            .entries
+            .iter()
+            .filter(|e| e.seq > after)
+            .cloned()
+            .collect();
+        Ok((backlog, receiver))
+    }
+    pub async fn sync(&self) -> Result<(), RusDbError> {
+        let state = self.state.lock().await;
+        state
+            .file
+            .sync_data()
+            .await
+            .map_err(|e| RusDbError::io(&self.path, e))
+    }
+}
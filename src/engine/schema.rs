@@ -0,0 +1,416 @@
+use bson::{Bson, Document};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+const BSON_TYPES: &[&str] = &[
+    "double",
+    "string",
+    "object",
+    "array",
+    "binData",
+    "objectId",
+    "bool",
+    "date",
+    "null",
+    "regex",
+    "int",
+    "long",
+    "decimal",
+    "timestamp",
+    "number",
+];
+
+/// How documents failing the validator are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationLevel {
+    /// The write is rejected.
+    #[default]
+    Strict,
+    /// The write goes through and the violations are logged.
+    Warn,
+}
+
+/// A single failed rule, located by a dotted path into the document.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "(root): {}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Additional {
+    Allowed,
+    Forbidden,
+    Schema(Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    bson_types: Vec<String>,
+    required: Vec<String>,
+    properties: Vec<(String, Node)>,
+    additional: Additional,
+    values: Option<Vec<Bson>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    min_items: Option<usize>,
+    max_items: Option<usize>,
+    pattern: Option<Regex>,
+    items: Option<Box<Node>>,
+}
+
+/// A compiled `$jsonSchema` validator. Supports `bsonType`, `required`,
+/// `properties`, `additionalProperties`, `enum`, `minimum`/`maximum`,
+/// `minLength`/`maxLength`, `minItems`/`maxItems`, `pattern` and `items`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Document", into = "Document")]
+pub struct Schema {
+    source: Document,
+    root: Node,
+}
+
+impl TryFrom<Document> for Schema {
+    type Error = String;
+
+    /// Accepts either the schema itself or a `{"$jsonSchema": ...}` wrapper.
+    fn try_from(doc: Document) -> Result<Self, Self::Error> {
+        let source = match doc.get_document("$jsonSchema") {
+            Ok(inner) if doc.len() == 1 => inner.clone(),
+            _ => doc,
+        };
+        let root = parse(&source, "")?;
+        Ok(Self { source, root })
+    }
+}
+
+impl From<Schema> for Document {
+    fn from(schema: Schema) -> Self {
+        schema.source
+    }
+}
+
+impl Schema {
+    pub fn source(&self) -> &Document {
+        &self.source
+    }
+    pub fn validate(&self, doc: &Document) -> Vec<Violation> {
+        let mut violations = vec![];
+        check(
+            &self.root,
+            &Bson::Document(doc.clone()),
+            "",
+            &mut violations,
+        );
+        violations
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(v) => Some(*v),
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+fn as_count(value: &Bson, keyword: &str, path: &str) -> Result<usize, String> {
+    match as_number(value) {
+        Some(n) if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+        _ => Err(format!(
+            "{} at {} must be a non-negative integer",
+            keyword,
+            display_path(path)
+        )),
+    }
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "(root)"
+    } else {
+        path
+    }
+}
+
+fn type_name(value: &Bson) -> &'static str {
+    match value {
+        Bson::Double(_) => "double",
+        Bson::String(_) => "string",
+        Bson::Document(_) => "object",
+        Bson::Array(_) => "array",
+        Bson::Binary(_) => "binData",
+        Bson::ObjectId(_) => "objectId",
+        Bson::Boolean(_) => "bool",
+        Bson::DateTime(_) => "date",
+        Bson::Null => "null",
+        Bson::RegularExpression(_) => "regex",
+        Bson::Int32(_) => "int",
+        Bson::Int64(_) => "long",
+        Bson::Decimal128(_) => "decimal",
+        Bson::Timestamp(_) => "timestamp",
+        _ => "unsupported",
+    }
+}
+
+fn type_matches(name: &str, value: &Bson) -> bool {
+    if name == "number" {
+        return matches!(
+            value,
+            Bson::Double(_) | Bson::Int32(_) | Bson::Int64(_) | Bson::Decimal128(_)
+        );
+    }
+    type_name(value) == name
+}
+
+fn parse(doc: &Document, path: &str) -> Result<Node, String> {
+    let mut node = Node {
+        bson_types: vec![],
+        required: vec![],
+        properties: vec![],
+        additional: Additional::Allowed,
+        values: None,
+        minimum: None,
+        maximum: None,
+        min_length: None,
+        max_length: None,
+        min_items: None,
+        max_items: None,
+        pattern: None,
+        items: None,
+    };
+    let at = display_path(path);
+    for (key, value) in doc {
+        match key.as_str() {
+            "bsonType" => {
+                node.bson_types = match value {
+                    Bson::String(name) => vec![name.clone()],
+                    Bson::Array(names) => names
+                        .iter()
+                        .map(|name| name.as_str().map(|n| n.to_string()))
+                        .collect::<Option<Vec<String>>>()
+                        .ok_or_else(|| {
+                            format!("bsonType at {} must be a string or an array of strings", at)
+                        })?,
+                    _ => {
+                        return Err(format!(
+                            "bsonType at {} must be a string or an array of strings",
+                            at
+                        ))
+                    }
+                };
+                if let Some(name) = node
+                    .bson_types
+                    .iter()
+                    .find(|name| !BSON_TYPES.contains(&name.as_str()))
+                {
+                    return Err(format!("unknown bsonType {} at {}", name, at));
+                }
+            }
+            "required" => {
+                node.required = value
+                    .as_array()
+                    .and_then(|names| {
+                        names
+                            .iter()
+                            .map(|name| name.as_str().map(|n| n.to_string()))
+                            .collect::<Option<Vec<String>>>()
+                    })
+                    .ok_or_else(|| format!("required at {} must be an array of strings", at))?;
+            }
+            "properties" => {
+                let properties = value
+                    .as_document()
+                    .ok_or_else(|| format!("properties at {} must be a document", at))?;
+                for (name, schema) in properties {
+                    let schema = schema.as_document().ok_or_else(|| {
+                        format!("schema for {} must be a document", join(path, name))
+                    })?;
+                    node.properties
+                        .push((name.clone(), parse(schema, &join(path, name))?));
+                }
+            }
+            "additionalProperties" => {
+                node.additional = match value {
+                    Bson::Boolean(true) => Additional::Allowed,
+                    Bson::Boolean(false) => Additional::Forbidden,
+                    Bson::Document(schema) => Additional::Schema(Box::new(parse(schema, path)?)),
+                    _ => {
+                        return Err(format!(
+                            "additionalProperties at {} must be a boolean or a document",
+                            at
+                        ))
+                    }
+                };
+            }
+            "enum" => {
+                node.values = Some(
+                    value
+                        .as_array()
+                        .ok_or_else(|| format!("enum at {} must be an array", at))?
+                        .clone(),
+                );
+            }
+            "minimum" | "maximum" => {
+                let n = as_number(value)
+                    .ok_or_else(|| format!("{} at {} must be a number", key, at))?;
+                if key == "minimum" {
+                    node.minimum = Some(n);
+                } else {
+                    node.maximum = Some(n);
+                }
+            }
+            "minLength" => node.min_length = Some(as_count(value, key, path)?),
+            "maxLength" => node.max_length = Some(as_count(value, key, path)?),
+            "minItems" => node.min_items = Some(as_count(value, key, path)?),
+            "maxItems" => node.max_items = Some(as_count(value, key, path)?),
+            "pattern" => {
+                let pattern = value
+                    .as_str()
+                    .ok_or_else(|| format!("pattern at {} must be a string", at))?;
+                node.pattern = Some(
+                    Regex::new(pattern).map_err(|e| format!("invalid pattern at {}: {}", at, e))?,
+                );
+            }
+            "items" => {
+                let schema = value
+                    .as_document()
+                    .ok_or_else(|| format!("items at {} must be a document", at))?;
+                node.items = Some(Box::new(parse(schema, &join(path, "items"))?));
+            }
+            "title" | "description" => {}
+            _ => return Err(format!("unsupported keyword {} at {}", key, at)),
+        }
+    }
+    Ok(node)
+}
+
+fn check(node: &Node, value: &Bson, path: &str, out: &mut Vec<Violation>) {
+    let mut fail = |message: String| {
+        out.push(Violation {
+            path: path.to_string(),
+            message,
+        })
+    };
+    if !node.bson_types.is_empty() && !node.bson_types.iter().any(|t| type_matches(t, value)) {
+        fail(format!(
+            "expected bsonType {}, found {}",
+            node.bson_types.join(" or "),
+            type_name(value)
+        ));
+        // The remaining rules assume the declared type.
+        return;
+    }
+    if let Some(values) = &node.values {
+        if !values.contains(value) {
+            fail(format!("{} is not one of the allowed values", value));
+        }
+    }
+    if let Some(n) = as_number(value) {
+        if let Some(min) = node.minimum {
+            if n < min {
+                fail(format!("{} is less than the minimum of {}", value, min));
+            }
+        }
+        if let Some(max) = node.maximum {
+            if n > max {
+                fail(format!("{} is greater than the maximum of {}", value, max));
+            }
+        }
+    }
+    match value {
+        Bson::String(s) => {
+            let len = s.chars().count();
+            if let Some(min) = node.min_length {
+                if len < min {
+                    fail(format!("length {} is shorter than minLength {}", len, min));
+                }
+            }
+            if let Some(max) = node.max_length {
+                if len > max {
+                    fail(format!("length {} is longer than maxLength {}", len, max));
+                }
+            }
+            if let Some(pattern) = &node.pattern {
+                if !pattern.is_match(s) {
+                    fail(format!("\"{}\" does not match pattern {}", s, pattern));
+                }
+            }
+        }
+        Bson::Array(items) => {
+            if let Some(min) = node.min_items {
+                if items.len() < min {
+                    fail(format!(
+                        "{} items is fewer than minItems {}",
+                        items.len(),
+                        min
+                    ));
+                }
+            }
+            if let Some(max) = node.max_items {
+                if items.len() > max {
+                    fail(format!(
+                        "{} items is more than maxItems {}",
+                        items.len(),
+                        max
+                    ));
+                }
+            }
+            if let Some(schema) = &node.items {
+                for (i, item) in items.iter().enumerate() {
+                    check(schema, item, &join(path, &i.to_string()), out);
+                }
+            }
+        }
+        Bson::Document(doc) => {
+            for name in &node.required {
+                if !doc.contains_key(name) {
+                    out.push(Violation {
+                        path: join(path, name),
+                        message: "required property is missing".to_string(),
+                    });
+                }
+            }
+            for (key, field) in doc {
+                let child = join(path, key);
+                match node.properties.iter().find(|(name, _)| name == key) {
+                    Some((_, schema)) => check(schema, field, &child, out),
+                    // `_id` is assigned by the server and always allowed.
+                    None if key == "_id" && path.is_empty() => {}
+                    None => match &node.additional {
+                        Additional::Allowed => {}
+                        Additional::Forbidden => out.push(Violation {
+                            path: child,
+                            message: "additional property is not allowed".to_string(),
+                        }),
+                        Additional::Schema(schema) => check(schema, field, &child, out),
+                    },
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,639 @@
+//! REST gateway for clients that cannot speak gRPC.
+//!
+//! Requests are translated into calls on `RusDbServ`, so validation,
+//! authorization and the engine code paths are exactly those of the gRPC
+//! service. Documents and filters are MongoDB Extended JSON; responses use
+//! its relaxed form. With `[grpc.tls]` configured the gateway is served over
+//! TLS with the same certificates.
+
+use crate::auth::{Authenticator, Identity};
+use crate::grpc::auth_server::Auth;
+use crate::grpc::rus_db_server::RusDb;
+use crate::grpc::*;
+use crate::tls::TlsState;
+use crate::{RusDbAuthServ, RusDbServ};
+use bson::{doc, Bson, Document};
+use engine::id::DocId;
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use rusdb::engine;
+use rustls::ServerConfig;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::{Infallible, TryFrom};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Status};
+
+/// Largest request body accepted, the BSON document size limit.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Longest a client gets to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HttpRequest = hyper::Request<Body>;
+type HttpResponse = hyper::Response<Body>;
+
+pub struct Gateway {
+    rusdb: RusDbServ,
+    auth_serv: RusDbAuthServ,
+    auth: Arc<Authenticator>,
+}
+
+/// What a request path names, below the optional `/databases/{db}` prefix.
+enum Route {
+    Authenticate,
+    Documents { collection: String },
+    Document { collection: String, id: String },
+    Query { collection: String },
+}
+
+/// Serves the gateway on `listener` until `shutdown` completes, over TLS
+/// when `tls` is given.
+pub async fn serve(
+    listener: TcpListener,
+    tls: Option<Arc<TlsState>>,
+    auth: Arc<Authenticator>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error> {
+    let gateway = Arc::new(Gateway {
+        rusdb: RusDbServ { auth: auth.clone() },
+        auth_serv: RusDbAuthServ { auth: auth.clone() },
+        auth,
+    });
+    match tls {
+        Some(tls) => run(tls_incoming(listener, tls.http_config()), gateway, shutdown).await,
+        None => run(AddrIncoming::from_listener(listener)?, gateway, shutdown).await,
+    }
+}
+
+async fn run<I>(
+    incoming: I,
+    gateway: Arc<Gateway>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error>
+where
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let service = make_service_fn(move |_: &I::Conn| {
+        let gateway = gateway.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.handle(request).await) }
+            }))
+        }
+    });
+    Server::builder(incoming)
+        .serve(service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+/// Completes the TLS handshake of connections accepted on `listener`, each
+/// in its own task so a slow client cannot hold up the others.
+fn tls_incoming(
+    listener: TcpListener,
+    config: ServerConfig,
+) -> impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error> {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let (sender, mut receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Unable to accept an HTTP connection: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+                // The server stopped accepting.
+                _ = sender.closed() => break,
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(stream).await;
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake with an HTTP client failed: {}", e),
+                    Err(_) => debug!("TLS handshake with an HTTP client timed out."),
+                }
+            });
+        }
+    });
+    accept::poll_fn(move |cx| receiver.poll_recv(cx).map(|stream| stream.map(Ok)))
+}
+
+fn decode(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn query_params(request: &HttpRequest) -> HashMap<String, String> {
+    request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (decode(key), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Splits the path into the database it selects and the route below it.
+fn route(path: &str) -> Option<(Option<String>, Route)> {
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .collect();
+    let mut segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+    let mut database = None;
+    if let ["databases", name, ..] = segments[..] {
+        database = Some(name.to_string());
+        segments.drain(..2);
+    }
+    let route = match segments[..] {
+        ["authenticate"] if database.is_none() => Route::Authenticate,
+        ["collections", name, "documents"] => Route::Documents {
+            collection: name.to_string(),
+        },
+        ["collections", name, "documents", id] => Route::Document {
+            collection: name.to_string(),
+            id: id.to_string(),
+        },
+        ["collections", name, "query"] => Route::Query {
+            collection: name.to_string(),
+        },
+        _ => return None,
+    };
+    Some((database, route))
+}
+
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+        Code::ResourceExhausted => StatusCode::INSUFFICIENT_STORAGE,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn respond(status: StatusCode, body: Value) -> HttpResponse {
+    hyper::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Errors carry the gRPC code along with its message.
+fn error(status: Status) -> HttpResponse {
+    respond(
+        http_status(status.code()),
+        json!({ "code": status.code() as i32, "message": status.message() }),
+    )
+}
+
+fn to_json(doc: Document) -> Value {
+    Bson::Document(doc).into_relaxed_extjson()
+}
+
+fn from_bytes(data: &[u8]) -> Result<Value, Status> {
+    let doc: Document = bson::from_slice(data)
+        .map_err(|e| Status::internal(format!("Unable to decode a stored document: {}", e)))?;
+    Ok(to_json(doc))
+}
+
+fn to_bytes(doc: &Document) -> Result<Vec<u8>, Status> {
+    bson::to_vec(doc).map_err(|e| Status::invalid_argument(format!("{}", e)))
+}
+
+fn to_document(value: Value, what: &str) -> Result<Document, Status> {
+    match value {
+        Value::Object(map) => Document::try_from(map)
+            .map_err(|e| Status::invalid_argument(format!("Invalid {}: {}.", what, e))),
+        _ => Err(Status::invalid_argument(format!(
+            "The {} must be a JSON object.",
+            what
+        ))),
+    }
+}
+
+/// Takes `field` out of a request body as a document, empty when absent.
+fn field_document(body: &mut Map<String, Value>, field: &str) -> Result<Document, Status> {
+    match body.remove(field) {
+        None | Some(Value::Null) => Ok(Document::new()),
+        Some(value) => to_document(value, field),
+    }
+}
+
+fn field_u32(body: &Map<String, Value>, field: &str) -> Result<Option<u32>, Status> {
+    match body.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .map(Some)
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("{} must be a non-negative integer.", field))
+            }),
+    }
+}
+
+fn field_bool(body: &Map<String, Value>, field: &str) -> Result<bool, Status> {
+    match body.get(field) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(value)) => Ok(*value),
+        Some(_) => Err(Status::invalid_argument(format!(
+            "{} must be a boolean.",
+            field
+        ))),
+    }
+}
+
+/// Query parameters as the JSON values they stand for, so they can be read
+/// like a request body.
+fn params_object(params: &HashMap<String, String>) -> Result<Map<String, Value>, Status> {
+    params
+        .iter()
+        .filter(|(key, _)| key.as_str() != "database")
+        .map(|(key, value)| {
+            let parsed = match key.as_str() {
+                "filter" => serde_json::from_str(value).map_err(|e| {
+                    Status::invalid_argument(format!("Invalid filter parameter: {}.", e))
+                })?,
+                _ => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.clone())),
+            };
+            Ok((key.clone(), parsed))
+        })
+        .collect()
+}
+
+async fn read_body(body: &mut Body) -> Result<Value, Status> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| Status::invalid_argument(format!("{}", e)))?;
+        if data.len() + chunk.len() > MAX_BODY {
+            return Err(Status::invalid_argument(format!(
+                "Request body is larger than {} bytes.",
+                MAX_BODY
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&data)
+        .map_err(|e| Status::invalid_argument(format!("Request body is not valid JSON: {}.", e)))
+}
+
+/// A body that must be a JSON object, or nothing at all.
+fn body_object(body: Value) -> Result<Map<String, Value>, Status> {
+    match body {
+        Value::Null => Ok(Map::new()),
+        Value::Object(map) => Ok(map),
+        _ => Err(Status::invalid_argument(
+            "Request body must be a JSON object.",
+        )),
+    }
+}
+
+fn id_filters(id: &str) -> Result<Vec<Vec<u8>>, Status> {
+    DocId::candidates(id)
+        .into_iter()
+        .map(|id| to_bytes(&doc! { "_id": Bson::from(id) }))
+        .collect()
+}
+
+fn not_found(id: &str) -> Status {
+    Status::not_found(format!("Document {} does not exist.", id))
+}
+
+impl Gateway {
+    async fn handle(&self, mut request: HttpRequest) -> HttpResponse {
+        let (database, route) = match route(request.uri().path()) {
+            Some(route) => route,
+            None => return error(Status::not_found("No such endpoint.")),
+        };
+        let params = query_params(&request);
+        let database = database
+            .or_else(|| params.get("database").cloned())
+            .unwrap_or_default();
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let method = request.method().clone();
+        let result = match read_body(request.body_mut()).await {
+            Ok(body) => {
+                let call = Call {
+                    gateway: self,
+                    authorization,
+                    database,
+                };
+                call.dispatch(method, route, params, body).await
+            }
+            Err(status) => Err(status),
+        };
+        result.unwrap_or_else(error)
+    }
+}
+
+/// One HTTP request on its way to the gRPC handlers.
+struct Call<'a> {
+    gateway: &'a Gateway,
+    authorization: Option<String>,
+    database: String,
+}
+
+impl Call<'_> {
+    /// Wraps `message` the way the gRPC interceptor would: the bearer token
+    /// is checked and the caller's identity attached.
+    fn request<T>(&self, message: T) -> Result<Request<T>, Status> {
+        let mut unary = Request::new(());
+        if let Some(value) = &self.authorization {
+            let value = MetadataValue::from_str(value)
+                .map_err(|_| Status::unauthenticated("Authorization header is not valid."))?;
+            unary.metadata_mut().insert("authorization", value);
+        }
+        let unary = self.gateway.auth.intercept(unary)?;
+        let mut request = Request::new(message);
+        if let Some(identity) = unary.extensions().get::<Identity>() {
+            request.extensions_mut().insert(identity.clone());
+        }
+        Ok(request)
+    }
+    async fn dispatch(
+        &self,
+        method: Method,
+        route: Route,
+        params: HashMap<String, String>,
+        body: Value,
+    ) -> Result<HttpResponse, Status> {
+        let rusdb = &self.gateway.rusdb;
+        let database = self.database.clone();
+        match (method, route) {
+            (Method::POST, Route::Authenticate) => {
+                let body = body_object(body)?;
+                let field = |name: &str| {
+                    body.get(name)
+                        .and_then(|v| v.as_str())
+                        .map(|v| v.to_string())
+                        .ok_or_else(|| Status::invalid_argument(format!("{} is required.", name)))
+                };
+                let message = AuthenticateRequest {
+                    username: field("username")?,
+                    password: field("password")?,
+                };
+                let response = self
+                    .gateway
+                    .auth_serv
+                    .authenticate(Request::new(message))
+                    .await?
+                    .into_inner();
+                Ok(respond(
+                    StatusCode::OK,
+                    json!({ "token": response.token, "expires": response.expires }),
+                ))
+            }
+            (Method::GET, Route::Documents { collection }) => {
+                let mut options = params_object(&params)?;
+                self.find(collection, &mut options).await
+            }
+            (Method::POST, Route::Query { collection }) => {
+                let mut options = body_object(body)?;
+                self.find(collection, &mut options).await
+            }
+            (Method::POST, Route::Documents { collection }) => {
+                let single = !body.is_array();
+                let documents = match body {
+                    Value::Array(values) => values,
+                    value => vec![value],
+                };
+                let documents = documents
+                    .into_iter()
+                    .map(|value| to_bytes(&to_document(value, "document")?))
+                    .collect::<Result<Vec<_>, Status>>()?;
+                let options = params_object(&params)?;
+                let message = InsertRequest {
+                    collection,
+                    documents,
+                    return_old: false,
+                    database,
+                    ordered: field_bool(&options, "ordered")?,
+                    atomic: field_bool(&options, "atomic")?,
+                };
+                let response = rusdb.insert(self.request(message)?).await?.into_inner();
+                if single {
+                    return match response.inserts.into_iter().next() {
+                        Some(InsertResponse { error: Some(e), .. }) => {
+                            Err(Status::new(Code::from(e.code), e.message))
+                        }
+                        Some(insert) => {
+                            Ok(respond(StatusCode::CREATED, json!({ "_id": insert.id })))
+                        }
+                        None => Err(Status::internal("Insert returned no result.")),
+                    };
+                }
+                let inserts: Vec<Value> = response
+                    .inserts
+                    .into_iter()
+                    .map(|insert| match insert.error {
+                        Some(e) => json!({
+                            "index": insert.index,
+                            "error": { "code": e.code, "message": e.message },
+                        }),
+                        None => json!({ "index": insert.index, "_id": insert.id }),
+                    })
+                    .collect();
+                Ok(respond(
+                    StatusCode::OK,
+                    json!({ "count": response.count, "inserts": inserts }),
+                ))
+            }
+            (Method::PATCH, Route::Documents { collection }) => {
+                let mut body = body_object(body)?;
+                let message = UpdateRequest {
+                    collection,
+                    filter: to_bytes(&field_document(&mut body, "filter")?)?,
+                    updates: to_bytes(&field_document(&mut body, "updates")?)?,
+                    limit: field_u32(&body, "limit")?,
+                    database,
+                };
+                let response = rusdb.update(self.request(message)?).await?.into_inner();
+                let documents = response
+                    .updated
+                    .iter()
+                    .map(|data| from_bytes(data))
+                    .collect::<Result<Vec<_>, Status>>()?;
+                Ok(respond(
+                    StatusCode::OK,
+                    json!({ "count": response.count, "documents": documents }),
+                ))
+            }
+            (Method::DELETE, Route::Documents { collection }) => {
+                let mut options = match body {
+                    Value::Null => params_object(&params)?,
+                    body => body_object(body)?,
+                };
+                let filter = field_document(&mut options, "filter")?;
+                let all = field_bool(&options, "all")?
+                    || params.get("all").map(String::as_str) == Some("true");
+                if filter.is_empty() && !all {
+                    return Err(Status::invalid_argument(
+                        "Pass a non-empty filter, or all=true to remove every document.",
+                    ));
+                }
+                let message = RemoveRequest {
+                    collection,
+                    filter: to_bytes(&filter)?,
+                    limit: field_u32(&options, "limit")?,
+                    database,
+                };
+                let response = rusdb.remove(self.request(message)?).await?.into_inner();
+                Ok(respond(StatusCode::OK, json!({ "count": response.count })))
+            }
+            (Method::GET, Route::Document { collection, id }) => {
+                let message = GetRequest {
+                    collection,
+                    id: id.clone(),
+                    database,
+                    id_document: None,
+                };
+                match rusdb
+                    .get(self.request(message)?)
+                    .await?
+                    .into_inner()
+                    .document
+                {
+                    Some(data) => Ok(respond(StatusCode::OK, from_bytes(&data)?)),
+                    None => Err(not_found(&id)),
+                }
+            }
+            (Method::PATCH, Route::Document { collection, id }) => {
+                let updates = to_bytes(&to_document(body, "updates")?)?;
+                for filter in id_filters(&id)? {
+                    let message = UpdateRequest {
+                        collection: collection.clone(),
+                        filter,
+                        updates: updates.clone(),
+                        limit: Some(1),
+                        database: database.clone(),
+                    };
+                    let response = rusdb.update(self.request(message)?).await?.into_inner();
+                    if let Some(data) = response.updated.first() {
+                        return Ok(respond(StatusCode::OK, from_bytes(data)?));
+                    }
+                }
+                Err(not_found(&id))
+            }
+            (Method::PUT, Route::Document { collection, id }) => {
+                let document = to_document(body, "document")?;
+                for filter in id_filters(&id)? {
+                    let message = ReplaceRequest {
+                        collection: collection.clone(),
+                        filter,
+                        document: to_bytes(&document)?,
+                        database: database.clone(),
+                    };
+                    let response = rusdb.replace(self.request(message)?).await?.into_inner();
+                    if let Some(data) = response.document {
+                        return Ok(respond(StatusCode::OK, from_bytes(&data)?));
+                    }
+                }
+                Err(not_found(&id))
+            }
+            (Method::DELETE, Route::Document { collection, id }) => {
+                for filter in id_filters(&id)? {
+                    let message = RemoveRequest {
+                        collection: collection.clone(),
+                        filter,
+                        limit: Some(1),
+                        database: database.clone(),
+                    };
+                    if rusdb
+                        .remove(self.request(message)?)
+                        .await?
+                        .into_inner()
+                        .count
+                        > 0
+                    {
+                        return Ok(respond(StatusCode::OK, json!({ "count": 1 })));
+                    }
+                }
+                Err(not_found(&id))
+            }
+            _ => Ok(respond(
+                StatusCode::METHOD_NOT_ALLOWED,
+                json!({
+                    "code": Code::Unimplemented as i32,
+                    "message": "Method not allowed on this endpoint.",
+                }),
+            )),
+        }
+    }
+    /// `Find` with `{filter, limit, reverse}` taken from `options`.
+    async fn find(
+        &self,
+        collection: String,
+        options: &mut Map<String, Value>,
+    ) -> Result<HttpResponse, Status> {
+        let filter = field_document(options, "filter")?;
+        let message = FindRequest {
+            collection,
+            filter: Some(to_bytes(&filter)?),
+            limit: field_u32(options, "limit")?,
+            database: self.database.clone(),
+            reverse: field_bool(options, "reverse")?,
+        };
+        let response = self
+            .gateway
+            .rusdb
+            .find(self.request(message)?)
+            .await?
+            .into_inner();
+        let documents = response
+            .documents
+            .iter()
+            .map(|data| from_bytes(data))
+            .collect::<Result<Vec<_>, Status>>()?;
+        Ok(respond(
+            StatusCode::OK,
+            json!({ "count": response.count, "documents": documents }),
+        ))
+    }
+}
@@ -0,0 +1,16 @@
+//! The rusdb document store as a library. `RusDbEngine::create` opens a data
+//! directory and `RusDbEngine::collection` hands out typed collections; the
+//! `rusdb` binary serves the same engine over gRPC.
+
+#[macro_use]
+extern crate log;
+
+pub mod collection;
+pub mod config;
+pub mod engine;
+pub mod write;
+
+pub use collection::{Collection, FindOptions};
+pub use engine::error::RusDbError;
+pub use engine::id::{DocId, IdStrategy};
+pub use engine::RusDbEngine;
@@ -1,5 +1,12 @@
-mod config;
-mod engine;
+// tonic::Status is large by design and is the error type for every handler.
+#![allow(clippy::result_large_err)]
+
+mod auth;
+mod http;
+mod mongo;
+mod reload;
+mod tls;
+mod uds;
 
 mod grpc {
     tonic::include_proto!("grpc");
@@ -9,18 +16,40 @@ mod grpc {
 extern crate log;
 
 use async_once::AsyncOnce;
+use auth::roles::{self, Grant, Privilege, Role, Scope};
+use auth::{AuthError, Authenticator};
 use bson::{doc, Document};
-use engine::RusDbEngine;
+use config::RusDbConfig;
+use engine::crypto::Cipher;
+use engine::error::RusDbError;
+use engine::id::{DocId, IdStrategy};
+use engine::oplog::{OpKind, OpLogEntry, ResumeError};
+use engine::schema::{Schema, ValidationLevel};
+use engine::{RusDbEngine, DEFAULT_DATABASE};
+use grpc::auth_server::{Auth, AuthServer};
 use grpc::rus_db_server::{RusDb, RusDbServer};
 use grpc::*;
 use lazy_static::lazy_static;
+use reload::{LevelLogger, Reloader};
+use rusdb::{config, engine, write, FindOptions};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::sync::Arc;
-use tokio::sync::broadcast::{channel as broadcast, Receiver, Sender};
-use tonic::{transport::Server, Request, Response, Status};
-use uuid::Uuid;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tls::TlsState;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinError, JoinHandle};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{transport::Server, Code, Request, Response, Status};
+use write::Writer;
 
 const PROJECT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -30,99 +59,383 @@ const GIT_DESCRIBE: &str = env!("GIT_DESCRIBE");
 
 const GIT_TAG: &str = env!("GIT_TAG");
 
+/// The effective configuration, set once by `main` before anything else
+/// starts.
+static CONFIG: OnceLock<RusDbConfig> = OnceLock::new();
+
+/// Set once the servers are configured, for SIGHUP and `ReloadConfig`.
+static RELOADER: OnceLock<Reloader> = OnceLock::new();
+
 lazy_static! {
     static ref ENGINE: AsyncOnce<Arc<RusDbEngine>> = AsyncOnce::new(async {
-        let conf = config::load().await;
-        RusDbEngine::create(&conf.engine).await
+        let conf = CONFIG
+            .get()
+            .expect("the configuration is loaded before the engine starts");
+        match RusDbEngine::create(&conf.engine).await {
+            Ok(engine) => engine,
+            Err(e) => {
+                error!("Unable to start the engine: {}", e);
+                eprintln!("Unable to start the engine: {}", e);
+                std::process::exit(1);
+            }
+        }
     });
-    static ref SHUTDOWN_CHANNEL: (Arc<Sender<bool>>, Receiver<bool>) = {
-        let (sender, receiver) = broadcast(1);
-        (Arc::new(sender), receiver)
-    };
+    /// Becomes true once shutdown begins and stays true.
+    static ref SHUTDOWN: watch::Sender<bool> = watch::Sender::new(false);
+}
+
+/// Seconds in-flight requests get to finish unless `[server]
+/// shutdown_timeout` says otherwise.
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+
+/// Resolves once shutdown has begun, right away if it already has.
+async fn shutdown_started() {
+    let _ = SHUTDOWN.subscribe().wait_for(|stopping| *stopping).await;
+}
+
+/// Encodes a document for a response. Documents come from the engine, so a
+/// failure is the server's fault rather than the request's.
+fn to_bytes<T: serde::Serialize>(doc: &T) -> Result<Vec<u8>, Status> {
+    bson::to_vec(doc).map_err(|e| Status::internal(format!("Unable to encode a document: {}", e)))
+}
+
+/// Sends a stream item, returning whether the stream should go on: not once
+/// the receiver is gone or after an error.
+async fn send<T>(tx: &mpsc::Sender<Result<T, Status>>, item: Result<T, Status>) -> bool {
+    let failed = item.is_err();
+    tx.send(item).await.is_ok() && !failed
+}
+
+#[derive(Debug)]
+pub struct RusDbServ {
+    auth: Arc<Authenticator>,
 }
 
-#[derive(Debug, Default)]
-pub struct RusDbServ;
+#[derive(Debug)]
+pub struct RusDbAuthServ {
+    auth: Arc<Authenticator>,
+}
+
+#[tonic::async_trait]
+impl Auth for RusDbAuthServ {
+    async fn authenticate(
+        &self,
+        request: Request<AuthenticateRequest>,
+    ) -> Result<Response<AuthenticateResponse>, Status> {
+        let req = request.get_ref();
+        if !self.auth.enabled() {
+            return Err(Status::failed_precondition(
+                "Authentication is not enabled on this server.",
+            ));
+        }
+        match self.auth.authenticate(&req.username, &req.password).await {
+            Some((token, expires)) => Ok(Response::new(AuthenticateResponse {
+                token,
+                expires: bson::DateTime::from_system_time(expires).timestamp_millis(),
+            })),
+            None => Err(Status::unauthenticated("Invalid username or password.")),
+        }
+    }
+}
 
 impl RusDbServ {
+    /// An empty name selects the default database.
+    pub fn sanitize_database(&self, name: &str) -> Option<String> {
+        engine::database_name(name)
+    }
     pub fn sanitize_collection(&self, name: &str) -> Option<String> {
-        let colname = name.to_lowercase();
-        let name_valid = !colname.contains(|c| match c {
-            '.' | '/' | '\\' => true,
-            _ => false,
-        });
-        if name_valid {
-            Some(colname)
-        } else {
-            None
-        }
+        engine::collection_name(name)
+    }
+}
+
+impl TryFrom<OpLogEntry> for WatchEvent {
+    type Error = Status;
+
+    fn try_from(entry: OpLogEntry) -> Result<Self, Self::Error> {
+        let op = match entry.op {
+            OpKind::Insert => Operation::Insert,
+            OpKind::Update => Operation::Update,
+            OpKind::Remove => Operation::Remove,
+        };
+        Ok(Self {
+            seq: entry.seq,
+            op: op as i32,
+            database: entry.database,
+            collection: entry.collection,
+            id: entry.id.to_string(),
+            document: entry.document.as_ref().map(to_bytes).transpose()?,
+            timestamp: entry.ts.timestamp_millis(),
+        })
+    }
+}
+
+impl TryFrom<&engine::CollectionOptions> for grpc::CollectionOptions {
+    type Error = Status;
+
+    fn try_from(options: &engine::CollectionOptions) -> Result<Self, Self::Error> {
+        Ok(Self {
+            flush_time: options.flush_time,
+            validator: options
+                .validator
+                .as_ref()
+                .map(|schema| to_bytes(schema.source()))
+                .transpose()?,
+            validation_level: options.validation_level.map(|level| match level {
+                ValidationLevel::Strict => grpc::ValidationLevel::Strict as i32,
+                ValidationLevel::Warn => grpc::ValidationLevel::Warn as i32,
+            }),
+            ttl: options.ttl.as_ref().map(|ttl| grpc::TtlOptions {
+                field: ttl.field.clone(),
+                expire_after_seconds: ttl.expire_after,
+            }),
+            capped: options.capped.as_ref().map(|capped| grpc::CappedOptions {
+                max_documents: capped.max_documents,
+                max_size: capped.max_size,
+            }),
+            id_strategy: options.id_strategy.map(|strategy| match strategy {
+                IdStrategy::UuidV4 => grpc::IdStrategy::UuidV4 as i32,
+                IdStrategy::UuidV7 => grpc::IdStrategy::UuidV7 as i32,
+                IdStrategy::Ulid => grpc::IdStrategy::Ulid as i32,
+                IdStrategy::ObjectId => grpc::IdStrategy::ObjectId as i32,
+                IdStrategy::Client => grpc::IdStrategy::Client as i32,
+            }),
+        })
+    }
+}
+
+impl TryFrom<grpc::CollectionOptions> for engine::CollectionOptions {
+    type Error = Status;
+
+    fn try_from(options: grpc::CollectionOptions) -> Result<Self, Self::Error> {
+        let validator =
+            match options.validator {
+                Some(data) => {
+                    let doc: Document = bson::from_slice(&data).map_err(|_| {
+                        Status::invalid_argument("Validator is not a valid BSON document.")
+                    })?;
+                    Some(Schema::try_from(doc).map_err(|e| {
+                        Status::invalid_argument(format!("Invalid validator: {}.", e))
+                    })?)
+                }
+                None => None,
+            };
+        let validation_level = match options.validation_level {
+            Some(level) => match grpc::ValidationLevel::from_i32(level) {
+                Some(grpc::ValidationLevel::Strict) => Some(ValidationLevel::Strict),
+                Some(grpc::ValidationLevel::Warn) => Some(ValidationLevel::Warn),
+                None => {
+                    return Err(Status::invalid_argument(format!(
+                        "{} is not a valid validation level.",
+                        level
+                    )))
+                }
+            },
+            None => None,
+        };
+        let ttl = match options.ttl {
+            Some(ttl) if ttl.field.is_empty() => {
+                return Err(Status::invalid_argument("TTL field must not be empty."))
+            }
+            Some(ttl) => Some(engine::TtlOptions {
+                field: ttl.field,
+                expire_after: ttl.expire_after_seconds,
+            }),
+            None => None,
+        };
+        let capped = match options.capped {
+            Some(capped)
+                if capped.max_documents.unwrap_or(0) == 0 && capped.max_size.unwrap_or(0) == 0 =>
+            {
+                return Err(Status::invalid_argument(
+                    "Capped collections need a non-zero max_documents or max_size.",
+                ))
+            }
+            Some(capped) => Some(engine::CappedOptions {
+                max_documents: capped.max_documents.filter(|max| *max > 0),
+                max_size: capped.max_size.filter(|max| *max > 0),
+            }),
+            None => None,
+        };
+        let id_strategy = match options.id_strategy {
+            Some(strategy) => match grpc::IdStrategy::from_i32(strategy) {
+                Some(grpc::IdStrategy::UuidV4) => Some(IdStrategy::UuidV4),
+                Some(grpc::IdStrategy::UuidV7) => Some(IdStrategy::UuidV7),
+                Some(grpc::IdStrategy::Ulid) => Some(IdStrategy::Ulid),
+                Some(grpc::IdStrategy::ObjectId) => Some(IdStrategy::ObjectId),
+                Some(grpc::IdStrategy::Client) => Some(IdStrategy::Client),
+                None => {
+                    return Err(Status::invalid_argument(format!(
+                        "{} is not a valid id strategy.",
+                        strategy
+                    )))
+                }
+            },
+            None => None,
+        };
+        Ok(Self {
+            flush_time: options.flush_time,
+            validator,
+            validation_level,
+            ttl,
+            capped,
+            id_strategy,
+        })
     }
 }
 
 #[tonic::async_trait]
 impl RusDb for RusDbServ {
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+    type TailStream = ReceiverStream<Result<TailResponse, Status>>;
+
     async fn insert(
         &self,
         request: Request<InsertRequest>,
     ) -> Result<Response<InsertResponses>, Status> {
         let req = request.get_ref();
-        let name = self.sanitize_collection(&req.collection);
-        let colname = {
-            if name.is_none() {
-                return Err(Status::invalid_argument(format!(
-                    "Collection name contains invalid characters."
-                )));
-            } else {
-                name.unwrap()
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
             }
         };
-        if req.documents.len() == 0 {
-            return Err(Status::invalid_argument(format!(
-                "Documents field must contain at least one document."
-            )));
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Write,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        if req.documents.is_empty() {
+            return Err(Status::invalid_argument(
+                "Documents field must contain at least one document.",
+            ));
         }
         let engine = ENGINE.get().await.clone();
         let mut responses: Vec<InsertResponse> = Vec::with_capacity(req.documents.len());
-        if let Some(_col) = engine.get_collection(&colname).await {
-            let mut col = _col.write().await;
+        if let Some(_col) = engine.auto_collection(&database, &colname, true).await? {
+            let options = engine.collection_options(&database, &colname).await?;
+            let writer = Writer {
+                engine: &engine,
+                options: options.as_deref(),
+                database: &database,
+                collection: &colname,
+            };
+            let mut prepared: Vec<Result<(DocId, Document), Status>> =
+                Vec::with_capacity(req.documents.len());
             for data in &req.documents {
-                match bson::from_slice::<Document>(data) {
-                    Ok(mut doc) => {
-                        if let Some(id) = doc.get("_id") {
-                            if bson::from_bson::<Uuid>(id.clone()).is_err() {
-                                let uid = Uuid::new_v4();
-                                doc.insert("_id", uid);
-                            }
-                        } else {
-                            let uid = Uuid::new_v4();
-                            doc.insert("_id", bson::to_bson(&uid).unwrap());
-                        }
-                        let id = bson::from_bson::<Uuid>(doc.get("_id").unwrap().clone()).unwrap();
-                        (*col).insert(id, doc.clone());
-                        if req.return_old {
-                            responses.push(InsertResponse {
-                                id: id.to_string(),
-                                document: Some(bson::to_vec(&doc).unwrap()),
-                            })
-                        } else {
-                            responses.push(InsertResponse {
-                                id: id.to_string(),
-                                document: None,
-                            });
-                        }
+                let result = write::prepare_document(writer.options, &database, &colname, data)
+                    .map_err(Status::from);
+                let failed = result.is_err();
+                prepared.push(result);
+                if failed && req.ordered {
+                    break;
+                }
+            }
+            let mut col = _col.write().await;
+            // Under the lock, so no other insert can take an id in between.
+            let mut seen = HashSet::new();
+            for result in prepared.iter_mut() {
+                if let Ok((id, _)) = result {
+                    if col.contains_key(id) || !seen.insert(id.clone()) {
+                        *result = Err(RusDbError::DuplicateId(id.to_string()).into());
                     }
-                    Err(_) => {
+                }
+            }
+            if req.ordered {
+                if let Some(failed) = prepared.iter().position(|result| result.is_err()) {
+                    prepared.truncate(failed + 1);
+                }
+            }
+            if req.atomic {
+                let failed: Vec<(usize, &Status)> = prepared
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, result)| result.as_ref().err().map(|e| (index, e)))
+                    .collect();
+                if !failed.is_empty() {
+                    let errors: Vec<String> = failed
+                        .iter()
+                        .map(|(index, e)| format!("{}: {}", index, e.message()))
+                        .collect();
+                    // Only repeated ids: retrying the same batch cannot help.
+                    let code = if failed.iter().all(|(_, e)| e.code() == Code::AlreadyExists) {
+                        Code::AlreadyExists
+                    } else {
+                        Code::InvalidArgument
+                    };
+                    return Err(Status::new(
+                        code,
+                        format!("Batch rejected, invalid documents at {}", errors.join("; ")),
+                    ));
+                }
+            }
+            let mut count = 0;
+            for (index, result) in prepared.into_iter().enumerate() {
+                let (id, doc) = match result {
+                    Ok(prepared) => prepared,
+                    Err(e) => {
+                        responses.push(InsertResponse {
+                            index: index as u32,
+                            id: String::new(),
+                            document: None,
+                            error: Some(InsertError {
+                                code: e.code() as i32,
+                                message: e.message().to_string(),
+                            }),
+                        });
                         continue;
                     }
+                };
+                if let Err(e) = writer
+                    .insert(&mut col, id.clone(), doc.clone())
+                    .await
+                    .map_err(Status::from)
+                {
+                    // The oplog could not be written; later documents would
+                    // fail the same way.
+                    responses.push(InsertResponse {
+                        index: index as u32,
+                        id: String::new(),
+                        document: None,
+                        error: Some(InsertError {
+                            code: e.code() as i32,
+                            message: e.message().to_string(),
+                        }),
+                    });
+                    break;
                 }
+                count += 1;
+                responses.push(InsertResponse {
+                    index: index as u32,
+                    id: id.to_string(),
+                    document: if req.return_old {
+                        Some(to_bytes(&doc)?)
+                    } else {
+                        None
+                    },
+                    error: None,
+                });
             }
+            writer.enforce_cap(&mut col).await?;
             Ok(Response::new(InsertResponses {
-                count: responses.len() as u32,
+                count,
                 inserts: responses,
             }))
         } else {
             Err(Status::not_found(format!(
-                "The collection could not be loaded."
+                "Collection {} does not exist.",
+                colname
             )))
         }
     }
@@ -131,228 +444,1397 @@ impl RusDb for RusDbServ {
         request: Request<UpdateRequest>,
     ) -> Result<Response<UpdateResponses>, Status> {
         let req = request.get_ref();
-        let name = self.sanitize_collection(&req.collection);
-        let colname = {
-            if name.is_none() {
-                return Err(Status::invalid_argument(format!(
-                    "Collection name contains invalid characters."
-                )));
-            } else {
-                name.unwrap()
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Write,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let filter = write::parse_document(&req.filter, "Filter")?;
+        let updates = write::parse_document(&req.updates, "Updates document")?;
+        let engine = ENGINE.get().await.clone();
+        let updated = engine
+            .collection::<Document>(&database, &colname)?
+            .update(&filter, &updates, req.limit)
+            .await?;
+        Ok(Response::new(UpdateResponses {
+            count: updated.len() as u32,
+            updated: updated.iter().map(to_bytes).collect::<Result<_, _>>()?,
+        }))
+    }
+    async fn replace(
+        &self,
+        request: Request<ReplaceRequest>,
+    ) -> Result<Response<ReplaceResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Write,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let filter = write::parse_document(&req.filter, "Filter")?;
+        let replacement = write::parse_document(&req.document, "Document")?;
+        let engine = ENGINE.get().await.clone();
+        let replaced = engine
+            .collection::<Document>(&database, &colname)?
+            .replace(&filter, &replacement)
+            .await?;
+        Ok(Response::new(ReplaceResponse {
+            count: replaced.is_some() as u32,
+            document: replaced.as_ref().map(to_bytes).transpose()?,
+        }))
+    }
+    async fn remove(
+        &self,
+        request: Request<RemoveRequest>,
+    ) -> Result<Response<RemoveResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Write,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let filter = write::parse_document(&req.filter, "Filter")?;
+        let engine = ENGINE.get().await.clone();
+        let count = engine
+            .collection::<Document>(&database, &colname)?
+            .remove(&filter, req.limit)
+            .await?;
+        Ok(Response::new(RemoveResponse { count }))
+    }
+    async fn find(&self, request: Request<FindRequest>) -> Result<Response<FindResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Read,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let filters = match &req.filter {
+            Some(data) => write::parse_document(data, "Filter")?,
+            None => Document::default(),
+        };
+        let engine = ENGINE.get().await.clone();
+        let res = engine
+            .collection::<Document>(&database, &colname)?
+            .find(
+                &filters,
+                FindOptions {
+                    limit: req.limit,
+                    reverse: req.reverse,
+                },
+            )
+            .await?;
+        Ok(Response::new(FindResponse {
+            count: res.len() as u32,
+            documents: res.iter().map(to_bytes).collect::<Result<_, _>>()?,
+        }))
+    }
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
             }
         };
-        let filter: Document = {
-            let data = &req.filter;
-            bson::from_slice(data).unwrap_or_default()
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
         };
-        let updates: Document = {
-            let data = &req.updates;
-            bson::from_slice(data).unwrap_or_default()
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Read,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let ids = match &req.id_document {
+            Some(data) => {
+                let doc: Document = bson::from_slice(data).map_err(|_| {
+                    Status::invalid_argument("Id document is not a valid BSON document.")
+                })?;
+                let id = doc
+                    .get("_id")
+                    .ok_or_else(|| Status::invalid_argument("Id document has no _id field."))?;
+                vec![DocId::try_from(id).map_err(|e| Status::invalid_argument(format!("{}.", e)))?]
+            }
+            None => DocId::candidates(&req.id),
         };
-        if updates.len() == 0 {
-            return Err(Status::invalid_argument("Updates document is empty."));
+        let engine = ENGINE.get().await.clone();
+        let col = engine.collection::<Document>(&database, &colname)?;
+        for id in &ids {
+            if let Some(doc) = col.get(id).await? {
+                return Ok(Response::new(GetResponse {
+                    document: Some(to_bytes(&doc)?),
+                }));
+            }
+        }
+        Ok(Response::new(GetResponse { document: None }))
+    }
+    async fn bulk_write(
+        &self,
+        request: Request<BulkWriteRequest>,
+    ) -> Result<Response<BulkWriteResponse>, Status> {
+        let req = request.get_ref();
+        if req.operations.is_empty() {
+            return Err(Status::invalid_argument(
+                "Operations field must contain at least one operation.",
+            ));
+        }
+        let mut targets: Vec<Result<(String, String, &write_operation::Op), Status>> =
+            Vec::with_capacity(req.operations.len());
+        for operation in &req.operations {
+            let target = match (
+                self.sanitize_database(&operation.database),
+                self.sanitize_collection(&operation.collection),
+                &operation.op,
+            ) {
+                (None, _, _) => Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                )),
+                (_, None, _) => Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                )),
+                (_, _, None) => Err(Status::invalid_argument("Operation is empty.")),
+                (Some(database), Some(colname), Some(op)) => Ok((database, colname, op)),
+            };
+            targets.push(target);
+        }
+        // Every collection is authorized up front so nothing is written for
+        // a request that would be denied part way through.
+        let mut namespaces: Vec<(&str, &str)> = targets
+            .iter()
+            .filter_map(|target| target.as_ref().ok())
+            .map(|(database, colname, _)| (database.as_str(), colname.as_str()))
+            .collect();
+        namespaces.sort_unstable();
+        namespaces.dedup();
+        for (database, colname) in namespaces {
+            self.auth
+                .authorize(
+                    &request,
+                    Privilege::Write,
+                    Scope::Collection(database, colname),
+                )
+                .await?;
         }
         let engine = ENGINE.get().await.clone();
-        if let Some(col) = engine.get_collection(&colname).await {
-            let mut lock = col.write().await;
-            let mut updated: Vec<Document> = Vec::with_capacity(req.limit.unwrap_or(10) as usize);
-            for (_, v) in &mut *lock {
-                let mut result = true;
-                if filter.len() > 0 {
-                    for (dk, dv) in &filter {
-                        if let Some(fv) = v.get(dk) {
-                            if !fv.eq(dv) {
-                                result = false;
-                                break;
+        let mut response = BulkWriteResponse::default();
+        let fail = |response: &mut BulkWriteResponse, index: usize, e: Status| {
+            response.errors.push(BulkWriteError {
+                index: index as u32,
+                code: e.code() as i32,
+                message: e.message().to_string(),
+            });
+            req.ordered
+        };
+        let mut start = 0;
+        'runs: while start < targets.len() {
+            let (database, colname) = match &targets[start] {
+                Ok((database, colname, _)) => (database.clone(), colname.clone()),
+                Err(e) => {
+                    if fail(&mut response, start, Status::new(e.code(), e.message())) {
+                        break;
+                    }
+                    start += 1;
+                    continue;
+                }
+            };
+            // Consecutive operations on one collection share a single lock.
+            let mut end = start + 1;
+            while end < targets.len()
+                && matches!(&targets[end], Ok((db, name, _)) if *db == database && *name == colname)
+            {
+                end += 1;
+            }
+            let run: Vec<(usize, &write_operation::Op)> = targets[start..end]
+                .iter()
+                .enumerate()
+                .filter_map(|(i, target)| target.as_ref().ok().map(|(_, _, op)| (start + i, *op)))
+                .collect();
+            start = end;
+            let create = run
+                .iter()
+                .any(|(_, op)| matches!(op, write_operation::Op::Insert(_)));
+            let col = match engine.auto_collection(&database, &colname, create).await {
+                Ok(Some(col)) => col,
+                Err(e) => {
+                    let e = Status::from(e);
+                    for (index, _) in &run {
+                        if fail(&mut response, *index, Status::new(e.code(), e.message())) {
+                            break 'runs;
+                        }
+                    }
+                    continue;
+                }
+                Ok(None) => {
+                    // Only inserts can fail here; the rest have nothing to match.
+                    for (index, op) in &run {
+                        if matches!(op, write_operation::Op::Insert(_)) {
+                            let e = Status::not_found(format!(
+                                "Collection {} does not exist.",
+                                colname
+                            ));
+                            if fail(&mut response, *index, e) {
+                                break 'runs;
                             }
-                        } else {
-                            result = false;
-                            break;
                         }
                     }
+                    continue;
                 }
-                if result {
-                    for (dk, dv) in &updates {
-                        if dk != "_id" {
-                            v.insert(dk, dv.clone());
+            };
+            let options = match engine.collection_options(&database, &colname).await {
+                Ok(options) => options,
+                Err(e) => {
+                    let e = Status::from(e);
+                    for (index, _) in &run {
+                        if fail(&mut response, *index, Status::new(e.code(), e.message())) {
+                            break 'runs;
                         }
                     }
-                    updated.push(v.clone());
-                    if req.limit.is_some() {
-                        if updated.len() == req.limit.unwrap() as usize {
-                            break;
+                    continue;
+                }
+            };
+            let writer = Writer {
+                engine: &engine,
+                options: options.as_deref(),
+                database: &database,
+                collection: &colname,
+            };
+            let mut lock = col.write().await;
+            let mut stop = false;
+            for (index, op) in run {
+                let result = match op {
+                    write_operation::Op::Insert(insert) => {
+                        match write::prepare_document(
+                            writer.options,
+                            &database,
+                            &colname,
+                            &insert.document,
+                        ) {
+                            Ok((id, doc)) => {
+                                let inserted = id.to_string();
+                                writer.insert(&mut lock, id, doc).await.map(|_| {
+                                    response.inserted_ids.push(inserted);
+                                    response.inserted += 1;
+                                })
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    write_operation::Op::Update(update) => {
+                        match (
+                            write::parse_document(&update.filter, "Filter"),
+                            write::parse_document(&update.updates, "Updates document"),
+                        ) {
+                            (Err(e), _) | (_, Err(e)) => Err(e),
+                            (Ok(_), Ok(updates)) if updates.is_empty() => {
+                                Err(RusDbError::Invalid("Updates document is empty.".into()))
+                            }
+                            (Ok(filter), Ok(updates)) => writer
+                                .update(&mut lock, &filter, &updates, update.limit)
+                                .await
+                                .map(|updated| response.updated += updated.len() as u32),
+                        }
+                    }
+                    write_operation::Op::Replace(replace) => {
+                        match (
+                            write::parse_document(&replace.filter, "Filter"),
+                            write::parse_document(&replace.document, "Document"),
+                        ) {
+                            (Ok(filter), Ok(replacement)) => writer
+                                .replace(&mut lock, &filter, replacement)
+                                .await
+                                .map(|replaced| response.replaced += replaced.is_some() as u32),
+                            (Err(e), _) | (_, Err(e)) => Err(e),
                         }
                     }
+                    write_operation::Op::Remove(remove) => {
+                        match write::parse_document(&remove.filter, "Filter") {
+                            Ok(filter) => writer
+                                .remove(&mut lock, &filter, remove.limit)
+                                .await
+                                .map(|removed| response.removed += removed),
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                if let Err(e) = result {
+                    if fail(&mut response, index, e.into()) {
+                        stop = true;
+                        break;
+                    }
                 }
             }
-            Ok(Response::new(UpdateResponses {
-                count: updated.len() as u32,
-                updated: updated
-                    .into_iter()
-                    .map(|v| bson::to_vec(&v).unwrap())
-                    .collect(),
-            }))
-        } else {
-            Err(Status::internal("unable to find collection."))
+            writer.enforce_cap(&mut lock).await?;
+            if stop {
+                break;
+            }
         }
+        Ok(Response::new(response))
     }
-    async fn remove(
+    async fn watch(
         &self,
-        request: Request<RemoveRequest>,
-    ) -> Result<Response<RemoveResponse>, Status> {
-        let req = request.get_ref();
-        let name = self.sanitize_collection(&req.collection);
-        let colname = {
-            if name.is_none() {
-                return Err(Status::invalid_argument(format!(
-                    "Collection name contains invalid characters."
-                )));
-            } else {
-                name.unwrap()
-            }
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let req = request.get_ref().clone();
+        let colname = match &req.collection {
+            Some(name) => match self.sanitize_collection(name) {
+                Some(name) => Some(name),
+                None => {
+                    return Err(Status::invalid_argument(
+                        "Collection name contains invalid characters.",
+                    ))
+                }
+            },
+            None => None,
         };
-        let filter: Document = {
-            let data = &req.filter;
-            bson::from_slice(data).unwrap_or_default()
+        // A collection without a database means one in the default database.
+        let database = match (&req.database, &colname) {
+            (Some(name), _) => match self.sanitize_database(name) {
+                Some(name) => Some(name),
+                None => {
+                    return Err(Status::invalid_argument(
+                        "Database name contains invalid characters.",
+                    ))
+                }
+            },
+            (None, Some(_)) => Some(DEFAULT_DATABASE.to_string()),
+            (None, None) => None,
+        };
+        let scope = match (&database, &colname) {
+            (Some(database), Some(name)) => Scope::Collection(database, name),
+            (Some(database), None) => Scope::Database(database),
+            _ => Scope::All,
         };
+        self.auth
+            .authorize(&request, Privilege::Read, scope)
+            .await?;
         let engine = ENGINE.get().await.clone();
-        if let Some(col) = engine.get_collection(&colname).await {
-            let mut lock = col.write().await;
-            let mut entries: Vec<Uuid> = Vec::with_capacity(req.limit.unwrap_or(10) as usize);
-            for (k, v) in &*lock {
-                let mut result = true;
-                if filter.len() > 0 {
-                    for (dk, dv) in &filter {
-                        if let Some(bv) = v.get(dk) {
-                            if !bv.eq(dv) {
-                                result = false;
+        let (backlog, mut live) =
+            engine
+                .oplog()
+                .resume(req.resume_after)
+                .await
+                .map_err(|e| match e {
+                    ResumeError::Truncated { .. } => Status::out_of_range(e.to_string()),
+                    ResumeError::Ahead { .. } => Status::invalid_argument(e.to_string()),
+                })?;
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            let wanted = |entry: &OpLogEntry| {
+                database.as_ref().is_none_or(|db| &entry.database == db)
+                    && colname
+                        .as_ref()
+                        .is_none_or(|name| &entry.collection == name)
+            };
+            let mut last_seq = req.resume_after.unwrap_or(0);
+            for entry in backlog {
+                last_seq = entry.seq;
+                if wanted(&entry) && !send(&tx, WatchEvent::try_from(entry)).await {
+                    return;
+                }
+            }
+            loop {
+                tokio::select! {
+                    _ = shutdown_started() => break,
+                    res = live.recv() => match res {
+                        Ok(entry) => {
+                            if entry.seq <= last_seq {
+                                continue;
+                            }
+                            last_seq = entry.seq;
+                            if wanted(&entry) && !send(&tx, WatchEvent::try_from(entry)).await {
                                 break;
                             }
-                        } else {
-                            result = false;
-                            break;
                         }
-                    }
-                }
-                if result {
-                    entries.push(k.clone());
-                    if req.limit.is_some() {
-                        if req.limit.unwrap() as usize == entries.len() {
+                        Err(RecvError::Lagged(_)) => {
+                            let _ = tx
+                                .send(Err(Status::resource_exhausted(format!(
+                                    "Watcher fell behind the oplog; resume after sequence {}.",
+                                    last_seq
+                                ))))
+                                .await;
                             break;
                         }
+                        Err(RecvError::Closed) => break,
                     }
                 }
             }
-            for uid in &entries {
-                (*lock).remove(uid);
-            }
-            Ok(Response::new(RemoveResponse {
-                count: entries.len() as u32,
-            }))
-        } else {
-            Err(Status::internal("unable to find collection."))
-        }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
-    async fn find(&self, request: Request<FindRequest>) -> Result<Response<FindResponse>, Status> {
+    async fn tail(
+        &self,
+        request: Request<TailRequest>,
+    ) -> Result<Response<Self::TailStream>, Status> {
         let req = request.get_ref();
-        let name = self.sanitize_collection(&req.collection);
-        let colname = {
-            if name.is_none() {
-                return Err(Status::invalid_argument(format!(
-                    "Collection name contains invalid characters."
-                )));
-            } else {
-                name.unwrap()
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
             }
         };
-        let filters: Document = {
-            if let Some(data) = &req.filter {
-                bson::from_slice(data).unwrap_or_default()
-            } else {
-                Document::default()
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
             }
         };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Read,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let filter: Document = match &req.filter {
+            Some(data) => write::parse_document(data, "Filter")?,
+            None => Document::default(),
+        };
         let engine = ENGINE.get().await.clone();
-        if let Some(col) = engine.get_collection(&colname).await {
-            let mut res: Vec<Document> = Vec::with_capacity(req.limit.unwrap_or(10) as usize);
+        let col = engine
+            .get_collection(&database, &colname)
+            .await?
+            .ok_or_else(|| RusDbError::CollectionNotFound(colname.clone()))?;
+        let capped = engine
+            .collection_options(&database, &colname)
+            .await?
+            .is_some_and(|options| options.capped.is_some());
+        if !capped {
+            return Err(Status::failed_precondition(format!(
+                "Collection {} is not capped.",
+                colname
+            )));
+        }
+        let matches = move |doc: &Document| filter.iter().all(|(k, v)| doc.get(k) == Some(v));
+        // Writers append to the oplog while holding the collection lock, so
+        // subscribing under the read lock neither misses nor repeats inserts.
+        let (existing, mut live) = {
             let lock = col.read().await;
-            for (_, doc) in &*lock {
-                let mut result = true;
-                if filters.len() > 0 {
-                    for (k, v) in &filters {
-                        if let Some(dv) = doc.get(k) {
-                            if !v.eq(dv) {
-                                result = false;
+            let (_, live) = engine
+                .oplog()
+                .resume(None)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let existing: Vec<Document> = (*lock)
+                .values()
+                .filter(|doc| matches(doc))
+                .cloned()
+                .collect();
+            (existing, live)
+        };
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            for doc in existing {
+                let response = to_bytes(&doc).map(|document| TailResponse { document });
+                if !send(&tx, response).await {
+                    return;
+                }
+            }
+            loop {
+                tokio::select! {
+                    _ = shutdown_started() => break,
+                    res = live.recv() => match res {
+                        Ok(entry) => {
+                            if entry.op != OpKind::Insert
+                                || entry.database != database
+                                || entry.collection != colname
+                            {
+                                continue;
+                            }
+                            let doc = match entry.document {
+                                Some(doc) if matches(&doc) => doc,
+                                _ => continue,
+                            };
+                            let response = to_bytes(&doc).map(|document| TailResponse { document });
+                            if !send(&tx, response).await {
                                 break;
                             }
-                        } else {
-                            result = false;
-                            break;
                         }
-                    }
-                }
-                if result {
-                    res.push(doc.clone());
-                    if let Some(limit) = req.limit {
-                        if res.len() == limit as usize {
+                        Err(RecvError::Lagged(_)) => {
+                            let _ = tx
+                                .send(Err(Status::resource_exhausted(
+                                    "Tailing cursor fell behind the collection.",
+                                )))
+                                .await;
                             break;
                         }
+                        Err(RecvError::Closed) => break,
                     }
                 }
             }
-            Ok(Response::new(FindResponse {
-                count: res.len() as u32,
-                documents: res.into_iter().map(|v| bson::to_vec(&v).unwrap()).collect(),
-            }))
-        } else {
-            Err(Status::internal("unable to find collection."))
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<CreateUserResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let req = request.get_ref();
+        if req.username.is_empty() || req.password.is_empty() {
+            return Err(Status::invalid_argument(
+                "Username and password must not be empty.",
+            ));
+        }
+        match self
+            .auth
+            .create_user(&req.username, &req.password, req.roles.clone())
+            .await
+        {
+            Ok(id) => Ok(Response::new(CreateUserResponse { id: id.to_string() })),
+            Err(e @ AuthError::UserExists(_)) => Err(Status::already_exists(e.to_string())),
+            Err(e @ AuthError::RoleNotFound(_)) => Err(Status::not_found(e.to_string())),
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
         }
     }
-    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+    async fn drop_user(
+        &self,
+        request: Request<DropUserRequest>,
+    ) -> Result<Response<DropUserResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
         let req = request.get_ref();
-        let name = self.sanitize_collection(&req.collection);
-        let colname = {
-            if name.is_none() {
-                return Err(Status::invalid_argument(format!(
-                    "Collection name contains invalid characters."
-                )));
-            } else {
-                name.unwrap()
+        match self.auth.drop_user(&req.username).await {
+            Ok(()) => Ok(Response::new(DropUserResponse { dropped: true })),
+            Err(AuthError::UserNotFound(_)) => {
+                Ok(Response::new(DropUserResponse { dropped: false }))
             }
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+    async fn create_role(
+        &self,
+        request: Request<CreateRoleRequest>,
+    ) -> Result<Response<CreateRoleResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let req = request.get_ref();
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("Role name must not be empty."));
+        }
+        let mut privileges: Vec<Grant> = Vec::with_capacity(req.privileges.len());
+        for grant in &req.privileges {
+            let privilege = match grpc::Privilege::from_i32(grant.privilege) {
+                Some(grpc::Privilege::Read) => Privilege::Read,
+                Some(grpc::Privilege::Write) => Privilege::Write,
+                Some(grpc::Privilege::Admin) => Privilege::Admin,
+                None => {
+                    return Err(Status::invalid_argument(format!(
+                        "{} is not a valid privilege.",
+                        grant.privilege
+                    )))
+                }
+            };
+            let pattern = |name: &str| {
+                if name.is_empty() {
+                    "*".to_string()
+                } else {
+                    name.to_lowercase()
+                }
+            };
+            privileges.push(Grant {
+                privilege,
+                database: pattern(&grant.database),
+                collection: pattern(&grant.collection),
+            });
+        }
+        let role = Role {
+            name: req.name.clone(),
+            privileges,
         };
+        match self.auth.create_role(role).await {
+            Ok(()) => Ok(Response::new(CreateRoleResponse {})),
+            Err(e @ AuthError::RoleExists(_)) => Err(Status::already_exists(e.to_string())),
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+    async fn drop_role(
+        &self,
+        request: Request<DropRoleRequest>,
+    ) -> Result<Response<DropRoleResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let req = request.get_ref();
+        if req.name == roles::ROOT_ROLE {
+            return Err(Status::invalid_argument("The root role cannot be dropped."));
+        }
+        match self.auth.drop_role(&req.name).await {
+            Ok(()) => Ok(Response::new(DropRoleResponse { dropped: true })),
+            Err(AuthError::RoleNotFound(_)) => {
+                Ok(Response::new(DropRoleResponse { dropped: false }))
+            }
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+    async fn grant_role(
+        &self,
+        request: Request<GrantRoleRequest>,
+    ) -> Result<Response<GrantRoleResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let req = request.get_ref();
+        match self.auth.set_role(&req.username, &req.role, true).await {
+            Ok(changed) => Ok(Response::new(GrantRoleResponse { changed })),
+            Err(e @ AuthError::UserNotFound(_)) | Err(e @ AuthError::RoleNotFound(_)) => {
+                Err(Status::not_found(e.to_string()))
+            }
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+    async fn revoke_role(
+        &self,
+        request: Request<RevokeRoleRequest>,
+    ) -> Result<Response<RevokeRoleResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let req = request.get_ref();
+        match self.auth.set_role(&req.username, &req.role, false).await {
+            Ok(changed) => Ok(Response::new(RevokeRoleResponse { changed })),
+            Err(e @ AuthError::UserNotFound(_)) => Err(Status::not_found(e.to_string())),
+            Err(AuthError::Engine(e)) => Err(e.into()),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+    async fn create_collection(
+        &self,
+        request: Request<CreateCollectionRequest>,
+    ) -> Result<Response<CreateCollectionResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Write,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let options = engine::CollectionOptions::try_from(req.options.clone().unwrap_or_default())?;
         let engine = ENGINE.get().await.clone();
-        if let Some(col) = engine.get_collection(&colname).await {
-            if let Ok(uid) = Uuid::from_str(&req.id) {
-                let lock = col.read().await;
-                if let Some(doc) = (*lock).get(&uid) {
-                    let data = bson::to_vec(doc).unwrap();
-                    Ok(Response::new(GetResponse {
-                        document: Some(data),
-                    }))
-                } else {
-                    Ok(Response::new(GetResponse { document: None }))
+        match engine.create_collection(&database, &colname, options).await {
+            Ok(_) => Ok(Response::new(CreateCollectionResponse { created: true })),
+            Err(RusDbError::CollectionExists(_)) if req.if_not_exists => {
+                Ok(Response::new(CreateCollectionResponse { created: false }))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    async fn list_databases(
+        &self,
+        request: Request<ListDatabasesRequest>,
+    ) -> Result<Response<ListDatabasesResponse>, Status> {
+        let engine = ENGINE.get().await.clone();
+        let mut databases: Vec<String> = vec![];
+        for database in engine.list_databases().await {
+            // Readable as a whole, or through at least one of its collections.
+            let mut allowed = self
+                .auth
+                .authorize(&request, Privilege::Read, Scope::Database(&database))
+                .await
+                .is_ok();
+            for name in engine.list_collections(&database).await {
+                if allowed {
+                    break;
                 }
-            } else {
-                Err(Status::invalid_argument(format!(
-                    "{} is not a valid Uuid",
-                    &req.id
-                )))
+                allowed = self
+                    .auth
+                    .authorize(
+                        &request,
+                        Privilege::Read,
+                        Scope::Collection(&database, &name),
+                    )
+                    .await
+                    .is_ok();
+            }
+            if allowed {
+                databases.push(database);
+            }
+        }
+        Ok(Response::new(ListDatabasesResponse { databases }))
+    }
+    async fn drop_database(
+        &self,
+        request: Request<DropDatabaseRequest>,
+    ) -> Result<Response<DropDatabaseResponse>, Status> {
+        let req = request.get_ref();
+        let database = match self.sanitize_collection(&req.database) {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        if database == engine::ADMIN_DATABASE {
+            return Err(Status::invalid_argument(
+                "The admin database cannot be dropped.",
+            ));
+        }
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::Database(&database))
+            .await?;
+        let engine = ENGINE.get().await.clone();
+        let dropped = engine.drop_database(&database).await;
+        Ok(Response::new(DropDatabaseResponse { dropped }))
+    }
+    async fn list_collections(
+        &self,
+        request: Request<ListCollectionsRequest>,
+    ) -> Result<Response<ListCollectionsResponse>, Status> {
+        let database = match self.sanitize_database(&request.get_ref().database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        let engine = ENGINE.get().await.clone();
+        let mut collections: Vec<String> = vec![];
+        for name in engine.list_collections(&database).await {
+            let allowed = self
+                .auth
+                .authorize(
+                    &request,
+                    Privilege::Read,
+                    Scope::Collection(&database, &name),
+                )
+                .await
+                .is_ok();
+            if allowed {
+                collections.push(name);
             }
-        } else {
-            Err(Status::internal("unable to find collection."))
         }
+        Ok(Response::new(ListCollectionsResponse { collections }))
+    }
+    async fn drop_collection(
+        &self,
+        request: Request<DropCollectionRequest>,
+    ) -> Result<Response<DropCollectionResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Admin,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let engine = ENGINE.get().await.clone();
+        let dropped = engine.drop_collection(&database, &colname).await;
+        Ok(Response::new(DropCollectionResponse { dropped }))
+    }
+    async fn rename_collection(
+        &self,
+        request: Request<RenameCollectionRequest>,
+    ) -> Result<Response<RenameCollectionResponse>, Status> {
+        let req = request.get_ref();
+        let (from, to) = match (
+            self.sanitize_collection(&req.collection),
+            self.sanitize_collection(&req.to),
+        ) {
+            (Some(from), Some(to)) if !to.is_empty() => (from, to),
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Admin,
+                Scope::Collection(&database, &from),
+            )
+            .await?;
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Admin,
+                Scope::Collection(&database, &to),
+            )
+            .await?;
+        let engine = ENGINE.get().await.clone();
+        engine.rename_collection(&database, &from, &to).await?;
+        Ok(Response::new(RenameCollectionResponse {}))
+    }
+    async fn collection_stats(
+        &self,
+        request: Request<CollectionStatsRequest>,
+    ) -> Result<Response<CollectionStatsResponse>, Status> {
+        let req = request.get_ref();
+        let colname = match self.sanitize_collection(&req.collection) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Collection name contains invalid characters.",
+                ))
+            }
+        };
+        let database = match self.sanitize_database(&req.database) {
+            Some(name) => name,
+            None => {
+                return Err(Status::invalid_argument(
+                    "Database name contains invalid characters.",
+                ))
+            }
+        };
+        self.auth
+            .authorize(
+                &request,
+                Privilege::Read,
+                Scope::Collection(&database, &colname),
+            )
+            .await?;
+        let engine = ENGINE.get().await.clone();
+        let options = engine
+            .collection_options(&database, &colname)
+            .await?
+            .map(|options| grpc::CollectionOptions::try_from(options.as_ref()))
+            .transpose()?;
+        match engine.collection_stats(&database, &colname).await? {
+            Some(stats) => Ok(Response::new(CollectionStatsResponse {
+                database,
+                collection: colname,
+                count: stats.count,
+                memory_size: stats.memory_size,
+                disk_size: stats.disk_size,
+                cached: stats.cached,
+                last_access: stats
+                    .last_access
+                    .map(|t| bson::DateTime::from_system_time(t).timestamp_millis()),
+                options,
+            })),
+            None => Err(Status::not_found(format!(
+                "Collection {} does not exist.",
+                colname
+            ))),
+        }
+    }
+    async fn reload_config(
+        &self,
+        request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        self.auth
+            .authorize(&request, Privilege::Admin, Scope::All)
+            .await?;
+        let reloader = RELOADER
+            .get()
+            .ok_or_else(|| Status::unavailable("The server is still starting."))?;
+        let outcome = reloader.reload().await?;
+        Ok(Response::new(ReloadConfigResponse {
+            applied: outcome.applied,
+            restart_required: outcome.restart_required,
+        }))
     }
 }
 
+async fn rotate_key(args: &[String]) -> i32 {
+    if args.len() != 3 {
+        eprintln!("Usage: rusdb rotate-key <data-dir> <old-key-file|-> <new-key-file|->");
+        eprintln!("Use - for a plaintext side. Stop the server before rotating keys.");
+        return 2;
+    }
+    let load = |path: &str| -> Result<Option<Cipher>, i32> {
+        if path == "-" {
+            return Ok(None);
+        }
+        Cipher::from_key_file(Path::new(path))
+            .map(Some)
+            .map_err(|e| {
+                eprintln!("{}", e);
+                1
+            })
+    };
+    let (old, new) = match (load(&args[1]), load(&args[2])) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(code), _) | (_, Err(code)) => return code,
+    };
+    match engine::rotate_key(Path::new(&args[0]), old.as_ref(), new.as_ref()).await {
+        Ok((collections, entries)) => {
+            println!(
+                "Re-encrypted {} collection files and {} oplog entries.",
+                collections, entries
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Key rotation failed: {}", e);
+            1
+        }
+    }
+}
+
+const USAGE: &str = "\
+Usage: rusdb [options]
+       rusdb check-config [options]
+       rusdb rotate-key <data-dir> <old-key-file|-> <new-key-file|->
+
+  --config <path>     Configuration file (default .rusdb.toml)
+  --data-dir <path>   Data directory, overriding engine.dir
+  --print-config      Print the effective configuration and exit
+  --help
+
+Every configuration value can also be set with an environment variable named
+after its key, e.g. RUSDB_ENGINE_CACHE_TIME=5 for engine.cache_time.";
+
+/// Command-line flags of the server.
+#[derive(Default)]
+struct Flags {
+    config: Option<PathBuf>,
+    data_dir: Option<String>,
+    print_config: bool,
+}
+
+fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut flags = Flags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value.", arg))
+        };
+        match arg.as_str() {
+            "--config" | "-c" => flags.config = Some(PathBuf::from(value()?)),
+            "--data-dir" => flags.data_dir = Some(value()?),
+            "--print-config" => flags.print_config = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown option {}.\n\n{}", arg, USAGE)),
+        }
+    }
+    Ok(flags)
+}
+
 use simplelog::*;
 
+/// Sets up logging and the engine and starts every configured listener.
+/// Each listener stops accepting once shutdown begins, and its handle
+/// completes when the requests it was serving have finished. Fails without
+/// starting any listener when one of them cannot be set up.
+async fn start(
+    conf: RusDbConfig,
+    flags: Flags,
+    stopped: mpsc::UnboundedSender<String>,
+) -> Result<Vec<JoinHandle<()>>, RusDbError> {
+    let log_conf = conf.logging.clone().unwrap_or_default();
+    let level = log_conf.log_level();
+    // The loggers pass everything and `LevelLogger` filters, so a reload can
+    // raise the level as well as lower it.
+    let mut loggers: Vec<Box<dyn SharedLogger + 'static>> =
+        vec![SimpleLogger::new(LevelFilter::Trace, Config::default())];
+    if let Some(log_path) = &log_conf.path {
+        let mut p = PathBuf::new();
+        p.push(log_path);
+        if !p.is_absolute() {
+            p = std::env::current_dir().map_err(|e| RusDbError::io(Path::new("."), e))?;
+            p.push(conf.engine.dir.as_deref().unwrap_or("./rusdb"));
+            p.push(log_path);
+        }
+        loggers.push(WriteLogger::new(
+            LevelFilter::Trace,
+            Config::default(),
+            File::create(&p).map_err(|e| RusDbError::io(&p, e))?,
+        ));
+    }
+    log::set_boxed_logger(Box::new(LevelLogger(CombinedLogger::new(loggers))))
+        .map_err(|e| RusDbError::Config(format!("unable to set up logging: {}", e)))?;
+    log::set_max_level(level);
+    match &conf.source {
+        Some(path) => info!("Loaded configuration from {}.", path.display()),
+        None => info!("No configuration file found, using the defaults."),
+    }
+    let _engine = ENGINE.get().await.clone();
+    // Everything that can fail is checked before the first listener starts,
+    // so a bad setup never leaves part of the server running.
+    let tls_state = match &conf.grpc.tls {
+        Some(tls) => Some(Arc::new(TlsState::load(tls).map_err(|e| {
+            RusDbError::Config(format!("unable to load TLS configuration: {}", e))
+        })?)),
+        None => None,
+    };
+    // Addresses were checked by config::load.
+    let tcp: Option<SocketAddr> = match (&conf.grpc.ip, conf.grpc.port) {
+        (Some(ip), Some(port)) => format!("{}:{}", ip, port).parse().ok(),
+        _ => None,
+    };
+    let http_addr: Option<SocketAddr> = match &conf.http {
+        Some(http) => Some(
+            format!("{}:{}", http.ip, http.port)
+                .parse()
+                .map_err(|e| RusDbError::Config(format!("invalid HTTP gateway address: {}", e)))?,
+        ),
+        None => None,
+    };
+    let auth = Arc::new(Authenticator::new(conf.auth.clone().unwrap_or_default()));
+    let mongo_addr: Option<SocketAddr> = match &conf.mongo {
+        Some(mongo) => Some(
+            format!("{}:{}", mongo.ip, mongo.port)
+                .parse()
+                .map_err(|e| {
+                    RusDbError::Config(format!("invalid MongoDB listener address: {}", e))
+                })?,
+        ),
+        None => None,
+    };
+    let unix = match &conf.grpc.unix_socket {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let incoming = uds::bind(&path, conf.grpc.unix_socket_mode)
+                .map_err(|e| RusDbError::io(&path, e))?;
+            Some((path, incoming))
+        }
+        None => None,
+    };
+    let tcp = match tcp {
+        Some(addr) => Some((addr, listen(addr).await?)),
+        None => None,
+    };
+    let http = match http_addr {
+        Some(addr) => Some((addr, listen(addr).await?)),
+        None => None,
+    };
+    let mongo = match mongo_addr {
+        Some(addr) => Some((addr, listen(addr).await?)),
+        None => None,
+    };
+    let mut server = Server::builder();
+    if let Some(state) = &tls_state {
+        server = server
+            .tls_config(state.server_config())
+            .map_err(|e| RusDbError::Config(format!("invalid TLS configuration: {}", e)))?;
+    }
+    auth.bootstrap().await;
+    let _ = RELOADER.set(Reloader::new(
+        flags.config,
+        flags.data_dir,
+        conf.clone(),
+        auth.clone(),
+        tls_state.clone(),
+    ));
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Unable to listen for SIGHUP, reloading is off: {}", e);
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => RELOADER.get().unwrap().reload_logged().await,
+                _ = shutdown_started() => break,
+            }
+        }
+    });
+    let mut listeners = vec![];
+    if let Some((addr, listener)) = http {
+        let auth = auth.clone();
+        info!(
+            "Starting HTTP gateway at {}{}...",
+            addr,
+            if tls_state.is_some() { " (TLS)" } else { "" }
+        );
+        listeners.push(spawn_listener(
+            format!("HTTP gateway at {}", addr),
+            &stopped,
+            http::serve(listener, tls_state.clone(), auth, shutdown_started()),
+        ));
+    }
+    if let Some((addr, listener)) = mongo {
+        info!("Starting MongoDB listener at {}...", addr);
+        let engine = ENGINE.get().await.clone();
+        listeners.push(spawn_listener(
+            format!("MongoDB listener at {}", addr),
+            &stopped,
+            mongo::serve(listener, engine, SHUTDOWN.subscribe()),
+        ));
+    }
+    if let Some((path, incoming)) = unix {
+        info!("Starting gRPC server at unix:{}...", path.display());
+        let auth = auth.clone();
+        let name = format!("gRPC server at unix:{}", path.display());
+        listeners.push(spawn_listener(name, &stopped, async move {
+            let rusdb_server = RusDbServ { auth: auth.clone() };
+            let auth_server = RusDbAuthServ { auth: auth.clone() };
+            let result = Server::builder()
+                .add_service(AuthServer::new(auth_server))
+                .add_service(RusDbServer::with_interceptor(rusdb_server, move |req| {
+                    auth.intercept(req)
+                }))
+                .serve_with_incoming_shutdown(incoming, shutdown_started())
+                .await;
+            uds::remove(&path);
+            result
+        }));
+    }
+    let (addr, listener) = match tcp {
+        Some(tcp) => tcp,
+        None => return Ok(listeners),
+    };
+    info!(
+        "Starting gRPC server at {}{}...",
+        addr,
+        if tls_state.is_some() { " (TLS)" } else { "" }
+    );
+    let rusdb_server = RusDbServ { auth: auth.clone() };
+    let auth_server = RusDbAuthServ { auth: auth.clone() };
+    let server = server
+        .add_service(AuthServer::new(auth_server))
+        .add_service(RusDbServer::with_interceptor(rusdb_server, move |req| {
+            auth.intercept(req)
+        }));
+    listeners.push(spawn_listener(
+        format!("gRPC server at {}", addr),
+        &stopped,
+        server.serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown_started()),
+    ));
+    Ok(listeners)
+}
+
+async fn listen(addr: SocketAddr) -> Result<TcpListener, RusDbError> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| RusDbError::Config(format!("unable to listen on {}: {}", addr, e)))
+}
+
+/// Runs a listener until it stops. One stopping before shutdown began,
+/// failed or not, is reported on `stopped` and takes the server down.
+fn spawn_listener<E: fmt::Display + Send + 'static>(
+    name: String,
+    stopped: &mpsc::UnboundedSender<String>,
+    serve: impl Future<Output = Result<(), E>> + Send + 'static,
+) -> JoinHandle<()> {
+    let stopped = stopped.clone();
+    tokio::spawn(async move {
+        let result = serve.await;
+        if let Err(e) = &result {
+            error!("{} failed: {}", name, e);
+        }
+        if !*SHUTDOWN.borrow() {
+            let _ = stopped.send(name);
+        }
+    })
+}
+
+/// The listeners `start` returned, or exits when it failed.
+fn started(
+    result: Result<Result<Vec<JoinHandle<()>>, RusDbError>, JoinError>,
+) -> Vec<JoinHandle<()>> {
+    let error = match result {
+        Ok(Ok(listeners)) => return listeners,
+        Ok(Err(e)) => e.to_string(),
+        Err(e) => e.to_string(),
+    };
+    error!("Unable to start the server: {}", error);
+    eprintln!("Unable to start the server: {}", error);
+    std::process::exit(1);
+}
+
+/// SIGINT and SIGTERM, either of which stops the server.
+struct StopSignals {
+    interrupt: Signal,
+    terminate: Signal,
+}
+
+impl StopSignals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+    /// Waits for the next signal and returns its name.
+    async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.interrupt.recv() => "SIGINT",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let command = args.get(1).map(|a| a.as_str());
+    let rotate = command == Some("rotate-key");
+    let check = command == Some("check-config");
+    let (flags, conf) = if rotate {
+        (Flags::default(), RusDbConfig::default())
+    } else {
+        let flags = parse_flags(&args[if check { 2 } else { 1 }..]).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        });
+        match config::load(flags.config.as_deref(), flags.data_dir.as_deref()).await {
+            Ok(conf) => (flags, conf),
+            Err(e) => {
+                eprintln!("Unable to load configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    if check {
+        match &conf.source {
+            Some(path) => println!("{} is valid.", path.display()),
+            None => println!("No configuration file found; the defaults are valid."),
+        }
+        return;
+    }
+    if flags.print_config {
+        let mut shown = conf.clone();
+        if let Some(password) = shown.auth.as_mut().and_then(|a| a.admin_password.as_mut()) {
+            *password = "********".to_string();
+        }
+        match toml::to_string(&shown) {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                eprintln!("Unable to print the configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     println!(
         "RusDB {} {}{}",
         PROJECT_VERSION,
         {
-            if GIT_TAG.len() > 0 {
+            if !GIT_TAG.is_empty() {
                 GIT_TAG.to_string()
             } else {
                 format!("rev {}", GIT_HASH)
@@ -366,66 +1848,73 @@ async fn main() {
             }
         }
     );
-    tokio::spawn(async {
-        let conf = config::load().await;
-        let log_conf = conf.logging.unwrap_or_default();
-        let level = log_conf.log_level();
-        let mut loggers: Vec<Box<(dyn SharedLogger + 'static)>> =
-            vec![SimpleLogger::new(level.clone(), Config::default())];
-        if let Some(log_path) = &log_conf.path {
-            let mut p = PathBuf::new();
-            p.push(log_path);
-            if !p.is_absolute() {
-                p = std::env::current_dir().unwrap();
-                p.push(&conf.engine.dir.unwrap_or("./rusdb".to_string()));
-                p.push(log_path);
-            }
-            match level {
-                log::LevelFilter::Off => {}
-                _ => loggers.push(WriteLogger::new(
-                    level.clone(),
-                    Config::default(),
-                    File::create(&p).unwrap(),
-                )),
+    if rotate {
+        std::process::exit(rotate_key(&args[2..]).await);
+    }
+    let _ = CONFIG.set(conf.clone());
+    let drain_time = Duration::from_secs(
+        conf.server
+            .shutdown_timeout
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+    );
+    let mut signals = match StopSignals::new() {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Unable to listen for stop signals: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (stopped_tx, mut stopped) = mpsc::unbounded_channel();
+    let mut startup = tokio::spawn(start(conf, flags, stopped_tx));
+    let mut listeners = None;
+    let mut failed = false;
+    loop {
+        tokio::select! {
+            result = &mut startup, if listeners.is_none() => listeners = Some(started(result)),
+            name = signals.recv() => {
+                info!("Received {}, shutting down...", name);
+                break;
+            }
+            Some(name) = stopped.recv() => {
+                error!("{} stopped, shutting down...", name);
+                failed = true;
+                break;
             }
         }
-        CombinedLogger::init(loggers).unwrap();
-        let _engine = ENGINE.get().await.clone();
-        info!(
-            "Starting gRPC server at {}:{}...",
-            conf.grpc.ip, conf.grpc.port
-        );
-        let addr = format!("{}:{}", conf.grpc.ip, conf.grpc.port)
-            .parse()
-            .unwrap();
-        let rusdb_server = RusDbServ::default();
-        Server::builder()
-            .add_service(RusDbServer::new(rusdb_server))
-            .serve_with_shutdown(addr, async {
-                let shutdown = SHUTDOWN_CHANNEL.0.clone();
-                let mut chan = shutdown.subscribe();
-                match chan.recv().await {
-                    _ => {}
-                }
-            })
-            .await
-            .unwrap();
-    });
-    match tokio::signal::ctrl_c().await {
-        Ok(()) => {
-            debug!("Shutdown signal received.");
+    }
+    SHUTDOWN.send_replace(true);
+    // Listeners started after this point stop right away, so once startup is
+    // done every one of them is draining.
+    let listeners = match listeners {
+        Some(listeners) => listeners,
+        None => started(startup.await),
+    };
+    let drain = async {
+        for listener in listeners {
+            let _ = listener.await;
+        }
+    };
+    tokio::select! {
+        drained = tokio::time::timeout(drain_time, drain) => {
+            if drained.is_err() {
+                warn!(
+                    "Requests still running after {}s are abandoned.",
+                    drain_time.as_secs()
+                );
+            }
         }
+        name = signals.recv() => {
+            warn!("Received {} while draining, abandoning running requests.", name);
+        }
+    }
+    match ENGINE.get().await.shutdown().await {
+        Ok(()) => info!("Shutdown complete."),
         Err(e) => {
-            error!("Unable to listen for shutdown signal: {}", e);
+            error!("Unable to sync the cache on shutdown: {}", e);
+            std::process::exit(1);
         }
     }
-    let shutdown = SHUTDOWN_CHANNEL.0.clone();
-    match shutdown.send(true) {
-        _ => loop {
-            if shutdown.receiver_count() <= 1 {
-                break;
-            }
-        },
+    if failed {
+        std::process::exit(1);
     }
-    info!("Shutdown complete.");
 }
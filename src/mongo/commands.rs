@@ -0,0 +1,662 @@
+//! The supported subset of MongoDB commands, mapped onto `Collection`.
+//!
+//! Filters are limited to what the engine matches: equality on top-level
+//! fields. Query operators are rejected instead of silently matching
+//! nothing.
+
+use super::wire::MAX_MESSAGE_SIZE;
+use bson::{doc, Bson, Document};
+use engine::error::RusDbError;
+use engine::RusDbEngine;
+use rusdb::{engine, write, Collection, FindOptions};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_BSON_SIZE: i32 = 16 * 1024 * 1024;
+/// Documents per batch when the client does not ask for a size.
+const DEFAULT_BATCH_SIZE: usize = 101;
+/// Room left in a reply for everything but the batch itself.
+const BATCH_HEADROOM: u64 = 16 * 1024;
+/// Cursors not read from for this long are closed, as in `mongod`.
+const CURSOR_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Wire version of MongoDB 6.0, the newest protocol features are read from.
+const MAX_WIRE_VERSION: i32 = 17;
+
+/// A command failure, reported with MongoDB's error codes.
+#[derive(Debug)]
+pub struct CommandError {
+    pub code: i32,
+    pub name: &'static str,
+    pub message: String,
+}
+
+impl CommandError {
+    fn new(code: i32, name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            name,
+            message: message.into(),
+        }
+    }
+    fn bad_value(message: impl Into<String>) -> Self {
+        Self::new(2, "BadValue", message)
+    }
+    fn failed_to_parse(message: impl Into<String>) -> Self {
+        Self::new(9, "FailedToParse", message)
+    }
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "ok": 0.0,
+            "errmsg": &self.message,
+            "code": self.code,
+            "codeName": self.name,
+        }
+    }
+    /// Entry of a write command's `writeErrors`.
+    fn write_error(&self, index: usize) -> Document {
+        doc! { "index": index as i32, "code": self.code, "errmsg": &self.message }
+    }
+}
+
+impl From<RusDbError> for CommandError {
+    fn from(err: RusDbError) -> Self {
+        match err {
+            RusDbError::CollectionNotFound(_) => {
+                Self::new(26, "NamespaceNotFound", err.to_string())
+            }
+            RusDbError::CollectionExists(_) => Self::new(48, "NamespaceExists", err.to_string()),
+            RusDbError::DuplicateId(id) => Self::new(
+                11000,
+                "DuplicateKey",
+                format!("E11000 duplicate key error collection: _id {}", id),
+            ),
+            RusDbError::Invalid(reason) => Self::bad_value(reason),
+            err => {
+                error!("MongoDB command failed: {}", err);
+                Self::new(1, "InternalError", err.to_string())
+            }
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, CommandError>;
+
+struct Cursor {
+    namespace: String,
+    docs: VecDeque<Document>,
+    touched: Instant,
+}
+
+/// State shared by every connection of the listener.
+pub struct Context {
+    engine: Arc<RusDbEngine>,
+    next_cursor: AtomicI64,
+    cursors: Mutex<HashMap<i64, Cursor>>,
+}
+
+fn int(body: &Document, key: &str) -> Result<Option<i64>> {
+    match body.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Int32(v)) => Ok(Some(*v as i64)),
+        Some(Bson::Int64(v)) => Ok(Some(*v)),
+        Some(Bson::Double(v)) if v.fract() == 0.0 => Ok(Some(*v as i64)),
+        Some(_) => Err(CommandError::bad_value(format!(
+            "{} must be an integer.",
+            key
+        ))),
+    }
+}
+
+fn boolean(body: &Document, key: &str, default: bool) -> bool {
+    match body.get(key) {
+        Some(Bson::Boolean(v)) => *v,
+        Some(Bson::Int32(v)) => *v != 0,
+        Some(Bson::Int64(v)) => *v != 0,
+        Some(Bson::Double(v)) => *v != 0.0,
+        _ => default,
+    }
+}
+
+fn document(body: &Document, key: &str) -> Result<Document> {
+    match body.get(key) {
+        None | Some(Bson::Null) => Ok(Document::new()),
+        Some(Bson::Document(doc)) => Ok(doc.clone()),
+        Some(_) => Err(CommandError::bad_value(format!(
+            "{} must be a document.",
+            key
+        ))),
+    }
+}
+
+/// The documents of an array argument such as `documents` or `updates`.
+fn documents(body: &Document, key: &str) -> Result<Vec<Document>> {
+    let values = body
+        .get_array(key)
+        .map_err(|_| CommandError::failed_to_parse(format!("{} must be an array.", key)))?;
+    values
+        .iter()
+        .map(|value| match value {
+            Bson::Document(doc) => Ok(doc.clone()),
+            _ => Err(CommandError::failed_to_parse(format!(
+                "{} must only contain documents.",
+                key
+            ))),
+        })
+        .collect()
+}
+
+/// Rejects anything but equality on top-level fields.
+fn check_filter(filter: &Document) -> Result<()> {
+    for (key, value) in filter {
+        if key.starts_with('$') {
+            return Err(CommandError::bad_value(format!(
+                "Query operator {} is not supported.",
+                key
+            )));
+        }
+        if key.contains('.') {
+            return Err(CommandError::bad_value(format!(
+                "Dotted field {} is not supported in filters.",
+                key
+            )));
+        }
+        if let Bson::Document(inner) = value {
+            if let Some(op) = inner.keys().find(|k| k.starts_with('$')) {
+                return Err(CommandError::bad_value(format!(
+                    "Query operator {} is not supported.",
+                    op
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies a top-level inclusion or exclusion projection.
+fn project(doc: Document, projection: &Document) -> Document {
+    let include_id = projection.get("_id").is_none_or(boolean_value);
+    let inclusive = projection
+        .iter()
+        .any(|(key, value)| key != "_id" && boolean_value(value));
+    doc.into_iter()
+        .filter(|(key, _)| match projection.get(key) {
+            _ if key == "_id" => include_id,
+            Some(value) => boolean_value(value),
+            None => !inclusive,
+        })
+        .collect()
+}
+
+fn boolean_value(value: &Bson) -> bool {
+    !matches!(
+        value,
+        Bson::Boolean(false) | Bson::Int32(0) | Bson::Int64(0) | Bson::Null
+    ) && value != &Bson::Double(0.0)
+}
+
+/// Takes up to `size` documents off `docs`, keeping the reply under the
+/// document size limit.
+fn take_batch(docs: &mut VecDeque<Document>, size: usize) -> Vec<Bson> {
+    let mut batch = vec![];
+    let mut bytes = 0;
+    while batch.len() < size {
+        let next = match docs.front() {
+            Some(doc) => engine::document_size(doc),
+            None => break,
+        };
+        if !batch.is_empty() && bytes + next > MAX_BSON_SIZE as u64 - BATCH_HEADROOM {
+            break;
+        }
+        bytes += next;
+        batch.push(Bson::Document(docs.pop_front().unwrap()));
+    }
+    batch
+}
+
+fn batch_size(value: Option<i64>) -> Result<Option<usize>> {
+    match value {
+        Some(size) if size < 0 => Err(CommandError::bad_value("batchSize must not be negative.")),
+        Some(size) => Ok(Some(size as usize)),
+        None => Ok(None),
+    }
+}
+
+impl Context {
+    pub fn new(engine: Arc<RusDbEngine>) -> Self {
+        Self {
+            engine,
+            next_cursor: AtomicI64::new(1),
+            cursors: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Runs `body` and returns the reply, which carries any error.
+    pub async fn run(&self, connection_id: i32, body: Document) -> Document {
+        self.dispatch(connection_id, &body)
+            .await
+            .unwrap_or_else(|e| e.to_document())
+    }
+    async fn dispatch(&self, connection_id: i32, body: &Document) -> Result<Document> {
+        let name = body
+            .keys()
+            .next()
+            .ok_or_else(|| CommandError::failed_to_parse("Command document is empty."))?
+            .as_str();
+        let database = body
+            .get_str("$db")
+            .map_err(|_| CommandError::failed_to_parse("Command has no $db field."))?;
+        let database = engine::database_name(database)
+            .ok_or_else(|| CommandError::new(73, "InvalidNamespace", "Invalid database name."))?;
+        match name {
+            "hello" | "isMaster" | "ismaster" => Ok(self.hello(name, connection_id)),
+            "ping" | "endSessions" => Ok(doc! { "ok": 1.0 }),
+            "buildInfo" | "buildinfo" => Ok(doc! {
+                "version": "6.0.0",
+                "versionArray": [6, 0, 0, 0],
+                "bits": 64,
+                "maxBsonObjectSize": MAX_BSON_SIZE,
+                "ok": 1.0,
+            }),
+            "listDatabases" => self.list_databases().await,
+            "listCollections" => self.list_collections(&database, body).await,
+            "insert" => self.insert(&database, body).await,
+            "find" => self.find(&database, body).await,
+            "getMore" => self.get_more(&database, body),
+            "killCursors" => self.kill_cursors(body),
+            "update" => self.update(&database, body).await,
+            "delete" => self.delete(&database, body).await,
+            "drop" => self.drop(&database, body).await,
+            _ => Err(CommandError::new(
+                59,
+                "CommandNotFound",
+                format!("no such command: '{}'", name),
+            )),
+        }
+    }
+    fn hello(&self, name: &str, connection_id: i32) -> Document {
+        let primary = if name == "hello" {
+            "isWritablePrimary"
+        } else {
+            "ismaster"
+        };
+        doc! {
+            primary: true,
+            "helloOk": true,
+            "maxBsonObjectSize": MAX_BSON_SIZE,
+            "maxMessageSizeBytes": MAX_MESSAGE_SIZE as i32,
+            "maxWriteBatchSize": 100_000,
+            "localTime": bson::DateTime::now(),
+            "logicalSessionTimeoutMinutes": 30,
+            "connectionId": connection_id,
+            "minWireVersion": 0,
+            "maxWireVersion": MAX_WIRE_VERSION,
+            "readOnly": false,
+            "ok": 1.0,
+        }
+    }
+    /// The collection named by the command's first field.
+    fn collection(&self, database: &str, body: &Document) -> Result<Collection> {
+        let (command, name) = body.iter().next().unwrap();
+        let name = name.as_str().ok_or_else(|| {
+            CommandError::new(
+                73,
+                "InvalidNamespace",
+                format!("{} needs a collection name.", command),
+            )
+        })?;
+        self.engine
+            .collection(database, name)
+            .map_err(|e| CommandError::new(73, "InvalidNamespace", e.to_string()))
+    }
+    /// Stores what is left of `docs` after the first batch as a cursor.
+    fn open_cursor(
+        &self,
+        namespace: String,
+        docs: Vec<Document>,
+        size: Option<usize>,
+        single: bool,
+    ) -> Document {
+        let mut docs = VecDeque::from(docs);
+        let batch = take_batch(&mut docs, size.unwrap_or(DEFAULT_BATCH_SIZE));
+        let mut id = 0;
+        if !docs.is_empty() && !single {
+            id = self.next_cursor.fetch_add(1, Ordering::Relaxed);
+            let mut cursors = self.cursors.lock().unwrap();
+            cursors.retain(|_, cursor| cursor.touched.elapsed() < CURSOR_TIMEOUT);
+            cursors.insert(
+                id,
+                Cursor {
+                    namespace: namespace.clone(),
+                    docs,
+                    touched: Instant::now(),
+                },
+            );
+        }
+        doc! {
+            "cursor": { "firstBatch": batch, "id": id, "ns": namespace },
+            "ok": 1.0,
+        }
+    }
+    async fn list_databases(&self) -> Result<Document> {
+        let databases: Vec<Document> = self
+            .engine
+            .list_databases()
+            .await
+            .into_iter()
+            .map(|name| doc! { "name": name, "sizeOnDisk": 0i64, "empty": false })
+            .collect();
+        Ok(doc! { "databases": databases, "totalSize": 0i64, "ok": 1.0 })
+    }
+    async fn list_collections(&self, database: &str, body: &Document) -> Result<Document> {
+        let filter = document(body, "filter")?;
+        let name_only = boolean(body, "nameOnly", false);
+        let collections: Vec<Document> = self
+            .engine
+            .list_collections(database)
+            .await
+            .into_iter()
+            .map(|name| {
+                if name_only {
+                    doc! { "name": name, "type": "collection" }
+                } else {
+                    doc! {
+                        "name": name,
+                        "type": "collection",
+                        "options": {},
+                        "info": { "readOnly": false },
+                    }
+                }
+            })
+            .filter(|info| write::matches(&filter, info))
+            .collect();
+        Ok(self.open_cursor(
+            format!("{}.$cmd.listCollections", database),
+            collections,
+            None,
+            true,
+        ))
+    }
+    async fn insert(&self, database: &str, body: &Document) -> Result<Document> {
+        let col = self.collection(database, body)?;
+        let docs = documents(body, "documents")?;
+        let ordered = boolean(body, "ordered", true);
+        let mut inserted = 0;
+        let mut errors = vec![];
+        for (index, doc) in docs.into_iter().enumerate() {
+            if let Err(e) = self.insert_one(&col, doc).await {
+                errors.push(e.write_error(index));
+                if ordered {
+                    break;
+                }
+                continue;
+            }
+            inserted += 1;
+        }
+        Ok(write_reply(inserted, errors))
+    }
+    async fn insert_one(&self, col: &Collection, doc: Document) -> Result<()> {
+        col.insert(vec![doc]).await?;
+        Ok(())
+    }
+    async fn find(&self, database: &str, body: &Document) -> Result<Document> {
+        let col = self.collection(database, body)?;
+        let filter = document(body, "filter")?;
+        check_filter(&filter)?;
+        let sort = document(body, "sort")?;
+        let reverse = match sort.get("$natural") {
+            _ if sort.is_empty() => false,
+            Some(order) if sort.len() == 1 => {
+                matches!(order.as_i32(), Some(o) if o < 0)
+                    || matches!(order.as_i64(), Some(o) if o < 0)
+                    || matches!(order.as_f64(), Some(o) if o < 0.0)
+            }
+            _ => {
+                return Err(CommandError::bad_value(
+                    "Only {$natural: 1} and {$natural: -1} sorts are supported.",
+                ))
+            }
+        };
+        let skip = int(body, "skip")?.unwrap_or(0).max(0) as usize;
+        // A negative limit asks for a single batch.
+        let limit = int(body, "limit")?.unwrap_or(0);
+        let single = limit < 0 || boolean(body, "singleBatch", false);
+        let limit = match limit.unsigned_abs() as usize {
+            0 => None,
+            limit => Some(u32::try_from(skip + limit).unwrap_or(u32::MAX)),
+        };
+        let projection = document(body, "projection")?;
+        let docs = col.find(&filter, FindOptions { limit, reverse }).await?;
+        let docs: Vec<Document> = docs
+            .into_iter()
+            .skip(skip)
+            .map(|doc| {
+                if projection.is_empty() {
+                    doc
+                } else {
+                    project(doc, &projection)
+                }
+            })
+            .collect();
+        Ok(self.open_cursor(
+            format!("{}.{}", database, col.name()),
+            docs,
+            batch_size(int(body, "batchSize")?)?,
+            single,
+        ))
+    }
+    fn get_more(&self, database: &str, body: &Document) -> Result<Document> {
+        let id = int(body, "getMore")?
+            .ok_or_else(|| CommandError::failed_to_parse("getMore needs a cursor id."))?;
+        let collection = body
+            .get_str("collection")
+            .map_err(|_| CommandError::failed_to_parse("getMore needs a collection name."))?;
+        // Unlike find, a getMore batch size of 0 means no limit.
+        let size = match batch_size(int(body, "batchSize")?)? {
+            Some(0) | None => usize::MAX,
+            Some(size) => size,
+        };
+        let mut cursors = self.cursors.lock().unwrap();
+        let cursor = match cursors.get_mut(&id) {
+            Some(cursor) if cursor.touched.elapsed() < CURSOR_TIMEOUT => cursor,
+            _ => {
+                cursors.remove(&id);
+                return Err(CommandError::new(
+                    43,
+                    "CursorNotFound",
+                    format!("cursor id {} not found", id),
+                ));
+            }
+        };
+        let namespace = cursor.namespace.clone();
+        if engine::collection_name(collection)
+            .map(|name| format!("{}.{}", database, name))
+            .as_deref()
+            != Some(namespace.as_str())
+        {
+            return Err(CommandError::new(
+                13,
+                "Unauthorized",
+                format!("cursor id {} belongs to another namespace", id),
+            ));
+        }
+        let batch = take_batch(&mut cursor.docs, size);
+        cursor.touched = Instant::now();
+        let id = if cursor.docs.is_empty() {
+            cursors.remove(&id);
+            0
+        } else {
+            id
+        };
+        Ok(doc! {
+            "cursor": { "nextBatch": batch, "id": id, "ns": namespace },
+            "ok": 1.0,
+        })
+    }
+    fn kill_cursors(&self, body: &Document) -> Result<Document> {
+        let ids = body
+            .get_array("cursors")
+            .map_err(|_| CommandError::failed_to_parse("cursors must be an array."))?;
+        let mut cursors = self.cursors.lock().unwrap();
+        let mut killed = vec![];
+        let mut not_found = vec![];
+        for id in ids {
+            let id = match id {
+                Bson::Int64(id) => *id,
+                Bson::Int32(id) => *id as i64,
+                _ => return Err(CommandError::bad_value("Cursor ids must be integers.")),
+            };
+            if cursors.remove(&id).is_some() {
+                killed.push(id);
+            } else {
+                not_found.push(id);
+            }
+        }
+        Ok(doc! {
+            "cursorsKilled": killed,
+            "cursorsNotFound": not_found,
+            "cursorsAlive": [],
+            "cursorsUnknown": [],
+            "ok": 1.0,
+        })
+    }
+    async fn update(&self, database: &str, body: &Document) -> Result<Document> {
+        let col = self.collection(database, body)?;
+        let updates = documents(body, "updates")?;
+        let ordered = boolean(body, "ordered", true);
+        let mut matched = 0;
+        let mut upserted = vec![];
+        let mut errors = vec![];
+        for (index, spec) in updates.iter().enumerate() {
+            match update_one(&col, spec).await {
+                Ok((n, None)) => matched += n,
+                Ok((_, Some(id))) => upserted.push(doc! { "index": index as i32, "_id": id }),
+                Err(e) => {
+                    errors.push(e.write_error(index));
+                    if ordered {
+                        break;
+                    }
+                }
+            }
+        }
+        let mut reply = write_reply(matched + upserted.len(), errors);
+        reply.insert("nModified", matched as i32);
+        if !upserted.is_empty() {
+            reply.insert("upserted", upserted);
+        }
+        Ok(reply)
+    }
+    async fn delete(&self, database: &str, body: &Document) -> Result<Document> {
+        let col = self.collection(database, body)?;
+        let deletes = documents(body, "deletes")?;
+        let ordered = boolean(body, "ordered", true);
+        let mut removed = 0;
+        let mut errors = vec![];
+        for (index, spec) in deletes.iter().enumerate() {
+            let result = async {
+                let filter = document(spec, "q")?;
+                check_filter(&filter)?;
+                let limit = match int(spec, "limit")? {
+                    Some(0) | None => None,
+                    Some(1) => Some(1),
+                    Some(_) => return Err(CommandError::failed_to_parse("limit must be 0 or 1.")),
+                };
+                Ok(col.remove(&filter, limit).await?)
+            }
+            .await;
+            match result {
+                Ok(n) => removed += n as usize,
+                Err(e) => {
+                    errors.push(e.write_error(index));
+                    if ordered {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(write_reply(removed, errors))
+    }
+    async fn drop(&self, database: &str, body: &Document) -> Result<Document> {
+        let col = self.collection(database, body)?;
+        if !self.engine.drop_collection(database, col.name()).await {
+            return Err(CommandError::new(26, "NamespaceNotFound", "ns not found"));
+        }
+        Ok(doc! {
+            "ns": format!("{}.{}", database, col.name()),
+            "nIndexesWas": 1,
+            "ok": 1.0,
+        })
+    }
+}
+
+fn write_reply(n: usize, errors: Vec<Document>) -> Document {
+    let mut reply = doc! { "n": n as i32, "ok": 1.0 };
+    if !errors.is_empty() {
+        reply.insert("writeErrors", errors);
+    }
+    reply
+}
+
+/// Applies one `update` statement, returning the number of documents
+/// changed, or the `_id` of the document it upserted.
+async fn update_one(col: &Collection, spec: &Document) -> Result<(usize, Option<Bson>)> {
+    let filter = document(spec, "q")?;
+    check_filter(&filter)?;
+    let update = match spec.get("u") {
+        Some(Bson::Document(update)) => update,
+        Some(Bson::Array(_)) => {
+            return Err(CommandError::bad_value(
+                "Pipeline updates are not supported.",
+            ))
+        }
+        _ => return Err(CommandError::failed_to_parse("Update needs a u document.")),
+    };
+    let multi = boolean(spec, "multi", false);
+    let (changed, upsert) = if update.keys().any(|k| k.starts_with('$')) {
+        let mut set = Document::new();
+        for (op, fields) in update {
+            let fields = match (op.as_str(), fields) {
+                ("$set", Bson::Document(fields)) => fields,
+                ("$set", _) => return Err(CommandError::failed_to_parse("$set needs a document.")),
+                _ => {
+                    return Err(CommandError::bad_value(format!(
+                        "Update operator {} is not supported.",
+                        op
+                    )))
+                }
+            };
+            for (key, value) in fields {
+                if key.contains('.') {
+                    return Err(CommandError::bad_value(format!(
+                        "Dotted field {} is not supported in updates.",
+                        key
+                    )));
+                }
+                set.insert(key, value.clone());
+            }
+        }
+        let limit = if multi { None } else { Some(1) };
+        let changed = col.update(&filter, &set, limit).await?.len();
+        let mut upsert = filter.clone();
+        upsert.extend(set);
+        (changed, upsert)
+    } else {
+        if multi {
+            return Err(CommandError::failed_to_parse(
+                "multi updates need update operators, not a replacement document.",
+            ));
+        }
+        let changed = col.replace(&filter, update).await?.is_some() as usize;
+        let mut upsert = update.clone();
+        if let (Some(id), false) = (filter.get("_id"), upsert.contains_key("_id")) {
+            upsert.insert("_id", id.clone());
+        }
+        (changed, upsert)
+    };
+    if changed == 0 && boolean(spec, "upsert", false) {
+        let ids = col.insert(vec![upsert]).await?;
+        return Ok((0, ids.into_iter().next().map(Bson::from)));
+    }
+    Ok((changed, None))
+}
@@ -0,0 +1,80 @@
+//! MongoDB wire protocol listener, so standard drivers and tools can use
+//! the subset of commands in `commands`.
+
+mod commands;
+mod wire;
+
+use commands::Context;
+use engine::RusDbEngine;
+use rusdb::engine;
+use std::io;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+/// Accepts connections on `listener` until `shutdown` turns true, then
+/// returns once every connection has finished the command it was running.
+pub async fn serve(
+    listener: TcpListener,
+    engine: Arc<RusDbEngine>,
+    shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let context = Arc::new(Context::new(engine));
+    let mut stop = shutdown.clone();
+    let mut connections = JoinSet::new();
+    let mut connection_id = 0;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    connection_id += 1;
+                    debug!("MongoDB connection {} from {}.", connection_id, peer);
+                    connections.spawn(connection(
+                        stream,
+                        context.clone(),
+                        connection_id,
+                        shutdown.clone(),
+                    ));
+                }
+                Err(e) => warn!("Unable to accept a MongoDB connection: {}", e),
+            },
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = stop.wait_for(|stopping| *stopping) => break,
+        }
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn connection(
+    mut stream: TcpStream,
+    context: Arc<Context>,
+    connection_id: i32,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let _ = stream.set_nodelay(true);
+    loop {
+        let message = tokio::select! {
+            message = wire::read_message(&mut stream) => message,
+            _ = shutdown.wait_for(|stopping| *stopping) => break,
+        };
+        let message = match message {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Closing MongoDB connection {}: {}", connection_id, e);
+                break;
+            }
+        };
+        let reply = context.run(connection_id, message.body.clone()).await;
+        if message.flags & wire::MORE_TO_COME != 0 {
+            continue;
+        }
+        if let Err(e) = wire::write_reply(&mut stream, &message, &reply).await {
+            debug!("Closing MongoDB connection {}: {}", connection_id, e);
+            break;
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Framing of MongoDB wire protocol messages.
+//!
+//! Only `OP_MSG` is spoken in full. Legacy `OP_QUERY` is read for the
+//! handshake drivers send before they know the server supports `OP_MSG`,
+//! and answered with `OP_REPLY`.
+
+use bson::{Bson, Document};
+use std::convert::TryInto;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const OP_REPLY: i32 = 1;
+pub const OP_QUERY: i32 = 2004;
+pub const OP_MSG: i32 = 2013;
+
+/// Largest message accepted or advertised, as in `mongod`.
+pub const MAX_MESSAGE_SIZE: usize = 48_000_000;
+
+const HEADER_SIZE: usize = 16;
+const CHECKSUM_PRESENT: u32 = 1;
+/// Set by clients sending unacknowledged writes, which get no reply.
+pub const MORE_TO_COME: u32 = 1 << 1;
+
+/// A command read off the wire.
+pub struct Message {
+    pub request_id: i32,
+    pub op_code: i32,
+    pub flags: u32,
+    /// The command with any document sequences folded in as arrays.
+    pub body: Document,
+}
+
+fn invalid(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
+
+/// Splits `n` bytes off the front of `data`.
+fn take<'a>(data: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if data.len() < n {
+        return Err(invalid("message is truncated"));
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Ok(head)
+}
+
+fn read_i32(data: &mut &[u8]) -> io::Result<i32> {
+    Ok(i32::from_le_bytes(take(data, 4)?.try_into().unwrap()))
+}
+
+fn read_cstring(data: &mut &[u8]) -> io::Result<String> {
+    let end = data
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| invalid("unterminated string"))?;
+    let s = std::str::from_utf8(&data[..end])
+        .map_err(|_| invalid("string is not UTF-8"))?
+        .to_string();
+    *data = &data[end + 1..];
+    Ok(s)
+}
+
+fn read_document(data: &mut &[u8]) -> io::Result<Document> {
+    if data.len() < 4 {
+        return Err(invalid("message is truncated"));
+    }
+    let size = i32::from_le_bytes(data[..4].try_into().unwrap());
+    if size < 5 {
+        return Err(invalid("invalid document size"));
+    }
+    let mut bytes = take(data, size as usize)?;
+    Document::from_reader(&mut bytes).map_err(|e| invalid(format!("invalid document: {}", e)))
+}
+
+/// Reads the next message, or `None` when the peer closed the connection
+/// between messages.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Message>> {
+    let mut header = [0u8; HEADER_SIZE];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut fields = &header[..];
+    let length = read_i32(&mut fields)? as usize;
+    let request_id = read_i32(&mut fields)?;
+    let _response_to = read_i32(&mut fields)?;
+    let op_code = read_i32(&mut fields)?;
+    if !(HEADER_SIZE..=MAX_MESSAGE_SIZE).contains(&length) {
+        return Err(invalid(format!("invalid message length {}", length)));
+    }
+    let mut payload = vec![0u8; length - HEADER_SIZE];
+    reader.read_exact(&mut payload).await?;
+    let mut data = &payload[..];
+    let (flags, body) = match op_code {
+        OP_MSG => {
+            let flags = read_i32(&mut data)? as u32;
+            if flags & CHECKSUM_PRESENT != 0 {
+                data = &data[..data.len().saturating_sub(4)];
+            }
+            (flags, read_sections(data)?)
+        }
+        OP_QUERY => {
+            let _flags = read_i32(&mut data)?;
+            let namespace = read_cstring(&mut data)?;
+            let _skip = read_i32(&mut data)?;
+            let _limit = read_i32(&mut data)?;
+            let mut query = read_document(&mut data)?;
+            // Drivers may wrap the command along with read preferences.
+            if let Ok(inner) = query.get_document("$query") {
+                query = inner.clone();
+            }
+            let database = namespace
+                .strip_suffix(".$cmd")
+                .ok_or_else(|| invalid("OP_QUERY is only supported for commands"))?;
+            query.insert("$db", database);
+            (0, query)
+        }
+        _ => return Err(invalid(format!("unsupported op code {}", op_code))),
+    };
+    Ok(Some(Message {
+        request_id,
+        op_code,
+        flags,
+        body,
+    }))
+}
+
+fn read_sections(mut data: &[u8]) -> io::Result<Document> {
+    let mut body = None;
+    let mut sequences = vec![];
+    while !data.is_empty() {
+        match take(&mut data, 1)?[0] {
+            0 => body = Some(read_document(&mut data)?),
+            1 => {
+                let size = read_i32(&mut data)?;
+                if size < 4 {
+                    return Err(invalid("invalid document sequence size"));
+                }
+                let mut section = take(&mut data, size as usize - 4)?;
+                let identifier = read_cstring(&mut section)?;
+                let mut docs = vec![];
+                while !section.is_empty() {
+                    docs.push(Bson::Document(read_document(&mut section)?));
+                }
+                sequences.push((identifier, docs));
+            }
+            kind => return Err(invalid(format!("unknown section kind {}", kind))),
+        }
+    }
+    let mut body = body.ok_or_else(|| invalid("message has no body"))?;
+    for (identifier, docs) in sequences {
+        body.insert(identifier, docs);
+    }
+    Ok(body)
+}
+
+/// Writes `reply` in answer to `request`, in the format it was asked in.
+pub async fn write_reply<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    request: &Message,
+    reply: &Document,
+) -> io::Result<()> {
+    let mut doc = vec![];
+    reply
+        .to_writer(&mut doc)
+        .map_err(|e| invalid(format!("unable to encode reply: {}", e)))?;
+    let mut payload = vec![];
+    let op_code = match request.op_code {
+        OP_QUERY => {
+            payload.extend_from_slice(&0i32.to_le_bytes()); // responseFlags
+            payload.extend_from_slice(&0i64.to_le_bytes()); // cursorID
+            payload.extend_from_slice(&0i32.to_le_bytes()); // startingFrom
+            payload.extend_from_slice(&1i32.to_le_bytes()); // numberReturned
+            OP_REPLY
+        }
+        _ => {
+            payload.extend_from_slice(&0u32.to_le_bytes()); // flagBits
+            payload.push(0);
+            OP_MSG
+        }
+    };
+    payload.extend_from_slice(&doc);
+    let mut message = Vec::with_capacity(HEADER_SIZE + payload.len());
+    message.extend_from_slice(&((HEADER_SIZE + payload.len()) as i32).to_le_bytes());
+    message.extend_from_slice(&0i32.to_le_bytes()); // requestID
+    message.extend_from_slice(&request.request_id.to_le_bytes());
+    message.extend_from_slice(&op_code.to_le_bytes());
+    message.extend_from_slice(&payload);
+    writer.write_all(&message).await?;
+    writer.flush().await
+}
@@ -0,0 +1,128 @@
+//! Re-reading the configuration of a running server, on SIGHUP or through
+//! the `ReloadConfig` RPC.
+
+use crate::auth::Authenticator;
+use crate::tls::TlsState;
+use crate::ENGINE;
+use log::{Log, Metadata, Record};
+use rusdb::config::{self, RusDbConfig, RELOADABLE};
+use rusdb::engine::error::RusDbError;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// What a reload changed.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Keys whose new values are in effect.
+    pub applied: Vec<String>,
+    /// Keys that differ from the values the server was started with, which
+    /// stay in use until it restarts.
+    pub restart_required: Vec<String>,
+}
+
+pub struct Reloader {
+    path: Option<PathBuf>,
+    data_dir: Option<String>,
+    /// The configuration the server was started with.
+    started: RusDbConfig,
+    /// The configuration most recently applied.
+    current: Mutex<RusDbConfig>,
+    auth: Arc<Authenticator>,
+    tls: Option<Arc<TlsState>>,
+}
+
+impl Reloader {
+    /// `path` and `data_dir` are the command-line flags the configuration
+    /// was first loaded with; environment overrides are applied again.
+    pub fn new(
+        path: Option<PathBuf>,
+        data_dir: Option<String>,
+        started: RusDbConfig,
+        auth: Arc<Authenticator>,
+        tls: Option<Arc<TlsState>>,
+    ) -> Self {
+        Self {
+            path,
+            data_dir,
+            current: Mutex::new(started.clone()),
+            started,
+            auth,
+            tls,
+        }
+    }
+    /// Loads and validates the configuration again and applies the keys in
+    /// `RELOADABLE`. TLS certificates are re-read from their configured
+    /// paths as well. Nothing is applied when the new configuration is
+    /// invalid or the certificates cannot be loaded.
+    pub async fn reload(&self) -> Result<Outcome, RusDbError> {
+        let next = config::load(self.path.as_deref(), self.data_dir.as_deref()).await?;
+        let mut current = self.current.lock().await;
+        if let Some(tls) = &self.tls {
+            tls.reload()
+                .map_err(|e| RusDbError::Config(format!("unable to reload TLS: {}", e)))?;
+        }
+        let outcome = Outcome {
+            applied: current
+                .changed_keys(&next)
+                .into_iter()
+                .filter(|key| RELOADABLE.contains(key))
+                .map(String::from)
+                .collect(),
+            restart_required: self
+                .started
+                .changed_keys(&next)
+                .into_iter()
+                .filter(|key| !RELOADABLE.contains(key))
+                .map(String::from)
+                .collect(),
+        };
+        log::set_max_level(next.logging.clone().unwrap_or_default().log_level());
+        ENGINE.get().await.reconfigure(&next.engine);
+        self.auth
+            .reconfigure(&next.auth.clone().unwrap_or_default());
+        *current = next;
+        Ok(outcome)
+    }
+    /// Reloads and logs the outcome, for SIGHUP.
+    pub async fn reload_logged(&self) {
+        match self.reload().await {
+            Ok(outcome) => {
+                if outcome.applied.is_empty() {
+                    info!("Reloaded the configuration, no settings changed.");
+                } else {
+                    info!(
+                        "Reloaded the configuration, applied {}.",
+                        outcome.applied.join(", ")
+                    );
+                }
+                if !outcome.restart_required.is_empty() {
+                    warn!(
+                        "Changes to {} take effect after a restart.",
+                        outcome.restart_required.join(", ")
+                    );
+                }
+            }
+            Err(e) => error!("Unable to reload the configuration: {}", e),
+        }
+    }
+}
+
+/// Passes records on to the wrapped loggers up to `log::max_level()`, the
+/// level a reload changes. Libraries logging through `tracing` bypass that
+/// maximum, so it is checked here as well.
+pub struct LevelLogger<L>(pub L);
+
+impl<L: Log> Log for LevelLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && self.0.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.0.log(record);
+        }
+    }
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
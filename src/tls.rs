@@ -0,0 +1,147 @@
+use crate::config::TlsConfig;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, Certificate,
+    ClientCertVerified, ClientCertVerifier, ClientHello, DistinguishedNames, NoClientAuth,
+    ResolvesServerCert, RootCertStore, ServerConfig, TLSError,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use tonic::transport::ServerTlsConfig;
+use x509_parser::prelude::*;
+
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// Certificates currently served by the gRPC listener and the HTTP gateway.
+///
+/// The rustls config only holds references to this state, so swapping the
+/// contents takes effect for every new handshake without rebinding.
+pub struct TlsState {
+    config: TlsConfig,
+    key: RwLock<CertifiedKey>,
+    verifier: RwLock<Arc<dyn ClientCertVerifier>>,
+}
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("unable to open {}: {}", path, e))
+}
+
+fn load_key(config: &TlsConfig) -> Result<CertifiedKey, String> {
+    let certs = pemfile::certs(&mut open(&config.cert)?)
+        .map_err(|_| format!("unable to parse certificates in {}", config.cert))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", config.cert));
+    }
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(&config.key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(&config.key)?).unwrap_or_default();
+    }
+    let key = match keys.first() {
+        Some(key) => sign::any_supported_type(key)
+            .map_err(|_| format!("unsupported private key type in {}", config.key))?,
+        None => return Err(format!("no private key found in {}", config.key)),
+    };
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+fn load_verifier(config: &TlsConfig) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    match &config.client_ca {
+        Some(path) => {
+            let mut store = RootCertStore::empty();
+            match store.add_pem_file(&mut open(path)?) {
+                Ok((added, _)) if added > 0 => {}
+                _ => return Err(format!("no usable CA certificates found in {}", path)),
+            }
+            if config.require_client_cert.unwrap_or(true) {
+                Ok(AllowAnyAuthenticatedClient::new(store))
+            } else {
+                Ok(AllowAnyAnonymousOrAuthenticatedClient::new(store))
+            }
+        }
+        None => Ok(NoClientAuth::new()),
+    }
+}
+
+impl TlsState {
+    pub fn load(config: &TlsConfig) -> Result<Self, String> {
+        Ok(Self {
+            config: config.clone(),
+            key: RwLock::new(load_key(config)?),
+            verifier: RwLock::new(load_verifier(config)?),
+        })
+    }
+    /// Re-reads the certificate, key and client CA from disk. On failure the
+    /// previously loaded material stays in use.
+    pub fn reload(&self) -> Result<(), String> {
+        let key = load_key(&self.config)?;
+        let verifier = load_verifier(&self.config)?;
+        *self.key.write().unwrap() = key;
+        *self.verifier.write().unwrap() = verifier;
+        Ok(())
+    }
+    pub fn server_config(self: &Arc<Self>) -> ServerTlsConfig {
+        let mut tls = ServerTlsConfig::new();
+        tls.rustls_server_config(self.rustls_config(ALPN_H2));
+        tls
+    }
+    /// The config for the HTTP gateway, which speaks HTTP/1.1.
+    pub fn http_config(self: &Arc<Self>) -> ServerConfig {
+        self.rustls_config(ALPN_HTTP1)
+    }
+    fn rustls_config(self: &Arc<Self>, protocol: &[u8]) -> ServerConfig {
+        let mut rustls_config = ServerConfig::new(Arc::new(ReloadingVerifier(self.clone())));
+        rustls_config.cert_resolver = Arc::new(ReloadingResolver(self.clone()));
+        rustls_config.set_protocols(&[protocol.to_vec()]);
+        rustls_config
+    }
+}
+
+struct ReloadingResolver(Arc<TlsState>);
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.0.key.read().unwrap().clone())
+    }
+}
+
+struct ReloadingVerifier(Arc<TlsState>);
+
+impl ReloadingVerifier {
+    fn inner(&self) -> Arc<dyn ClientCertVerifier> {
+        self.0.verifier.read().unwrap().clone()
+    }
+}
+
+impl ClientCertVerifier for ReloadingVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner().offer_client_auth()
+    }
+    fn client_auth_mandatory(&self, sni: Option<&webpki::DNSName>) -> Option<bool> {
+        self.inner().client_auth_mandatory(sni)
+    }
+    fn client_auth_root_subjects(
+        &self,
+        sni: Option<&webpki::DNSName>,
+    ) -> Option<DistinguishedNames> {
+        self.inner().client_auth_root_subjects(sni)
+    }
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        sni: Option<&webpki::DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        self.inner().verify_client_cert(presented_certs, sni)
+    }
+}
+
+/// Extracts the subject common name from a DER encoded client certificate.
+pub fn subject_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|cn| cn.to_string())
+}
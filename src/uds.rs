@@ -0,0 +1,118 @@
+//! gRPC over a Unix domain socket, for clients on the same host.
+
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::Connected;
+
+/// A socket connection tonic can serve.
+pub struct UnixStream(tokio::net::UnixStream);
+
+impl Connected for UnixStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Listens on `path`, replacing a socket left behind by a server that did
+/// not shut down cleanly. A socket another process still accepts on, or a
+/// file that is not a socket, is left alone and reported instead.
+pub fn bind(
+    path: &Path,
+    mode: Option<u32>,
+) -> io::Result<impl Stream<Item = io::Result<UnixStream>>> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another process is listening on the socket",
+                ));
+            }
+            debug!("Removing stale socket {}.", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the path exists and is not a socket",
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = match mode {
+        Some(mode) => bind_private(path, mode)?,
+        None => UnixListener::bind(path)?,
+    };
+    Ok(UnixListenerStream::new(listener).map(|stream| stream.map(UnixStream)))
+}
+
+/// Binds in a directory only this process can enter and moves the socket to
+/// `path` once it has `mode`, so no one can connect while it still has the
+/// permissions the umask gave it.
+fn bind_private(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the socket path has no file name",
+        )
+    })?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = parent.join(format!(".rusdb-{}", std::process::id()));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join(name);
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    if bound.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    let _ = fs::remove_dir(&dir);
+    bound
+}
+
+/// Removes the socket file once the server stopped listening on it.
+pub fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Unable to remove socket {}: {}", path.display(), e);
+    }
+}
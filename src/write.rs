@@ -0,0 +1,352 @@
+//! Writes applied to a collection whose lock the caller already holds, shared
+//! by `Collection` and the batched RPCs of the server.
+
+use crate::engine::error::RusDbError;
+use crate::engine::id::DocId;
+use crate::engine::oplog::OpKind;
+use crate::engine::schema::ValidationLevel;
+use crate::engine::{self, CollectionOptions, Documents, RusDbEngine};
+use bson::Document;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// Decodes a BSON document sent by a client; `what` names it in the error.
+pub fn parse_document(data: &[u8], what: &str) -> Result<Document, RusDbError> {
+    bson::from_slice(data)
+        .map_err(|_| RusDbError::Invalid(format!("{} is not a valid BSON document.", what)))
+}
+
+/// A document matches when every field of the filter is present and equal.
+pub fn matches(filter: &Document, doc: &Document) -> bool {
+    filter.iter().all(|(k, v)| doc.get(k) == Some(v))
+}
+
+/// Checks documents against the collection validator. At the `strict` level
+/// the first failing document rejects the request; at `warn` it is logged.
+pub fn validate_documents<'a>(
+    options: Option<&CollectionOptions>,
+    database: &str,
+    collection: &str,
+    docs: impl IntoIterator<Item = &'a Document>,
+) -> Result<(), RusDbError> {
+    for doc in docs {
+        validate_document(options, database, collection, doc)?;
+    }
+    Ok(())
+}
+
+pub fn validate_document(
+    options: Option<&CollectionOptions>,
+    database: &str,
+    collection: &str,
+    doc: &Document,
+) -> Result<(), RusDbError> {
+    let (schema, level) = match options {
+        Some(CollectionOptions {
+            validator: Some(schema),
+            validation_level,
+            ..
+        }) => (schema, validation_level.unwrap_or_default()),
+        _ => return Ok(()),
+    };
+    let violations = schema.validate(doc);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let details: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+    let id = doc.get("_id").map(|id| id.to_string()).unwrap_or_default();
+    match level {
+        ValidationLevel::Strict => Err(RusDbError::Invalid(format!(
+            "Document {} failed validation for {}.{}: {}",
+            id,
+            database,
+            collection,
+            details.join("; ")
+        ))),
+        ValidationLevel::Warn => {
+            warn!(
+                "Document {} failed validation for {}.{}: {}",
+                id,
+                database,
+                collection,
+                details.join("; ")
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Parses one document of an insert and prepares it like `prepare`.
+pub fn prepare_document(
+    options: Option<&CollectionOptions>,
+    database: &str,
+    collection: &str,
+    data: &[u8],
+) -> Result<(DocId, Document), RusDbError> {
+    let doc: Document = bson::from_slice(data)
+        .map_err(|e| RusDbError::Invalid(format!("Invalid BSON document: {}.", e)))?;
+    prepare(options, database, collection, doc)
+}
+
+/// Assigns the `_id` of a document about to be inserted and checks it
+/// against the collection's validator and size cap.
+pub fn prepare(
+    options: Option<&CollectionOptions>,
+    database: &str,
+    collection: &str,
+    mut doc: Document,
+) -> Result<(DocId, Document), RusDbError> {
+    if !doc.contains_key("_id") {
+        let strategy = options
+            .and_then(|options| options.id_strategy)
+            .unwrap_or_default();
+        match strategy.generate() {
+            Some(id) => {
+                doc.insert("_id", id);
+            }
+            None => {
+                return Err(RusDbError::Invalid(format!(
+                    "Documents in {}.{} must carry an _id.",
+                    database, collection
+                )))
+            }
+        }
+    }
+    let id = DocId::try_from(doc.get("_id").unwrap())
+        .map_err(|e| RusDbError::Invalid(format!("{}.", e)))?;
+    validate_document(options, database, collection, &doc)?;
+    if let Some(max) = options
+        .and_then(|options| options.capped.as_ref())
+        .and_then(|capped| capped.max_size)
+    {
+        if engine::document_size(&doc) > max {
+            return Err(RusDbError::Invalid(format!(
+                "Document is larger than the {} byte cap of {}.{}.",
+                max, database, collection
+            )));
+        }
+    }
+    Ok((id, doc))
+}
+
+/// Checks that none of `ids` is in the collection already or repeated
+/// among them, so a batch can be rejected before any of it is written.
+pub fn check_duplicates<'a>(
+    docs: &Documents,
+    ids: impl IntoIterator<Item = &'a DocId>,
+) -> Result<(), RusDbError> {
+    let mut seen = HashSet::new();
+    for id in ids {
+        if docs.contains_key(id) || !seen.insert(id) {
+            return Err(RusDbError::DuplicateId(id.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// One collection being written to, with the options its writes obey.
+pub struct Writer<'a> {
+    pub engine: &'a RusDbEngine,
+    pub options: Option<&'a CollectionOptions>,
+    pub database: &'a str,
+    pub collection: &'a str,
+}
+
+impl Writer<'_> {
+    /// Records a write in the oplog. Callers log before touching the
+    /// collection so a failed append leaves the documents unchanged.
+    async fn log(&self, op: OpKind, id: DocId, doc: Option<Document>) -> Result<(), RusDbError> {
+        self.engine
+            .oplog()
+            .append(op, self.database, self.collection, id, doc)
+            .await?;
+        Ok(())
+    }
+    /// Adds a new document. An `_id` already in the collection is rejected
+    /// rather than overwritten.
+    pub async fn insert(
+        &self,
+        docs: &mut Documents,
+        id: DocId,
+        doc: Document,
+    ) -> Result<(), RusDbError> {
+        if docs.contains_key(&id) {
+            return Err(RusDbError::DuplicateId(id.to_string()));
+        }
+        self.log(OpKind::Insert, id.clone(), Some(doc.clone()))
+            .await?;
+        docs.insert(id, doc);
+        Ok(())
+    }
+    /// Sets the fields of `updates` on up to `limit` matching documents.
+    /// Nothing is written unless every updated document passes validation.
+    pub async fn update(
+        &self,
+        docs: &mut Documents,
+        filter: &Document,
+        updates: &Document,
+        limit: Option<u32>,
+    ) -> Result<Vec<Document>, RusDbError> {
+        let mut updated: Vec<(DocId, Document)> = Vec::with_capacity(limit.unwrap_or(10) as usize);
+        for (k, v) in docs.iter() {
+            if !matches(filter, v) {
+                continue;
+            }
+            let mut doc = v.clone();
+            for (dk, dv) in updates {
+                if dk != "_id" {
+                    doc.insert(dk, dv.clone());
+                }
+            }
+            updated.push((k.clone(), doc));
+            if limit.is_some_and(|limit| updated.len() == limit as usize) {
+                break;
+            }
+        }
+        validate_documents(
+            self.options,
+            self.database,
+            self.collection,
+            updated.iter().map(|(_, doc)| doc),
+        )?;
+        for (k, doc) in &updated {
+            self.log(OpKind::Update, k.clone(), Some(doc.clone()))
+                .await?;
+            docs.insert(k.clone(), doc.clone());
+        }
+        Ok(updated.into_iter().map(|(_, doc)| doc).collect())
+    }
+    /// Swaps the first matching document for `replacement`, keeping its `_id`.
+    pub async fn replace(
+        &self,
+        docs: &mut Documents,
+        filter: &Document,
+        mut replacement: Document,
+    ) -> Result<Option<Document>, RusDbError> {
+        let found = docs
+            .iter()
+            .find(|(_, doc)| matches(filter, doc))
+            .map(|(k, doc)| (k.clone(), doc.get("_id").cloned()));
+        let (id, old_id) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        if let Some(old_id) = old_id {
+            match replacement.get("_id") {
+                Some(new_id) if new_id != &old_id => {
+                    return Err(RusDbError::Invalid(
+                        "The _id field cannot be changed.".to_string(),
+                    ))
+                }
+                _ => {
+                    replacement.insert("_id", old_id);
+                }
+            }
+        }
+        validate_document(self.options, self.database, self.collection, &replacement)?;
+        self.log(OpKind::Update, id.clone(), Some(replacement.clone()))
+            .await?;
+        docs.insert(id, replacement.clone());
+        Ok(Some(replacement))
+    }
+    /// Removes up to `limit` matching documents and returns how many went.
+    pub async fn remove(
+        &self,
+        docs: &mut Documents,
+        filter: &Document,
+        limit: Option<u32>,
+    ) -> Result<u32, RusDbError> {
+        let mut entries: Vec<DocId> = Vec::with_capacity(limit.unwrap_or(10) as usize);
+        for (k, v) in docs.iter() {
+            if matches(filter, v) {
+                entries.push(k.clone());
+                if limit.is_some_and(|limit| limit as usize == entries.len()) {
+                    break;
+                }
+            }
+        }
+        // Removed in one pass; documents already logged go even when a later
+        // append fails.
+        let mut removed = HashSet::with_capacity(entries.len());
+        let mut result = Ok(());
+        for id in entries {
+            if let Err(e) = self.log(OpKind::Remove, id.clone(), None).await {
+                result = Err(e);
+                break;
+            }
+            removed.insert(id);
+        }
+        docs.retain(|id, _| !removed.contains(id));
+        result.map(|_| removed.len() as u32)
+    }
+    /// Drops the oldest documents of a capped collection that no longer fit
+    /// its limits, recording each one in the oplog like a regular removal.
+    /// Documents are only dropped once their removal is logged.
+    pub async fn enforce_cap(&self, docs: &mut Documents) -> Result<(), RusDbError> {
+        let capped = match self.options.and_then(|options| options.capped.as_ref()) {
+            Some(capped) => capped,
+            None => return Ok(()),
+        };
+        let count = capped.excess(docs);
+        let ids: Vec<DocId> = docs.keys().take(count).cloned().collect();
+        for (logged, id) in ids.into_iter().enumerate() {
+            if let Err(e) = self.log(OpKind::Remove, id, None).await {
+                docs.drain(..logged);
+                return Err(e);
+            }
+        }
+        docs.drain(..count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn parse_document_accepts_bson_documents() {
+        let data = bson::to_vec(&doc! { "a": 1 }).unwrap();
+        assert_eq!(parse_document(&data, "Filter").unwrap(), doc! { "a": 1 });
+        assert_eq!(
+            parse_document(&bson::to_vec(&doc! {}).unwrap(), "Filter").unwrap(),
+            doc! {}
+        );
+    }
+
+    #[test]
+    fn parse_document_rejects_malformed_data() {
+        for data in [&b""[..], b"not bson", &[5, 0, 0, 0, 1]] {
+            match parse_document(data, "Filter") {
+                Err(RusDbError::Invalid(message)) => {
+                    assert_eq!(message, "Filter is not a valid BSON document.")
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_needs_every_filter_field() {
+        let doc = doc! { "a": 1, "b": "x" };
+        assert!(matches(&doc! {}, &doc));
+        assert!(matches(&doc! { "a": 1 }, &doc));
+        assert!(!matches(&doc! { "a": 1, "b": "y" }, &doc));
+        assert!(!matches(&doc! { "c": 1 }, &doc));
+    }
+
+    #[test]
+    fn prepare_stores_a_generated_uuid_as_a_string() {
+        let (id, doc) = prepare(None, "db", "col", doc! { "a": 1 }).unwrap();
+        let stored = doc.get_str("_id").expect("a string _id");
+        assert_eq!(id, DocId::String(stored.to_string()));
+    }
+
+    #[test]
+    fn prepare_keeps_a_client_id() {
+        let (id, doc) = prepare(None, "db", "col", doc! { "_id": 7, "a": 1 }).unwrap();
+        assert_eq!(id, DocId::Int(7));
+        assert_eq!(doc.get_i32("_id"), Ok(7));
+    }
+}